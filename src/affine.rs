@@ -0,0 +1,168 @@
+use std::ops::Mul;
+
+use quicksilver::geom::{Transform, Vector};
+
+use usvg;
+
+// A general 2D affine transform in SVG matrix(a b c d e f) order.
+// quicksilver's Transform can only be built from translate/rotate/scale,
+// so anything coming out of the SVG (skews, gradientTransform, ...) goes
+// through this instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Affine {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine{ a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    pub fn translate(v: Vector) -> Affine {
+        Affine{ e: v.x, f: v.y, ..Affine::IDENTITY }
+    }
+
+    pub fn scale(v: Vector) -> Affine {
+        Affine{ a: v.x, d: v.y, ..Affine::IDENTITY }
+    }
+
+    // Clockwise on screen, in degrees like quicksilver's Transform::rotate
+    pub fn rotate(degrees: f32) -> Affine {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Affine{ a: cos, b: sin, c: -sin, d: cos, ..Affine::IDENTITY }
+    }
+
+    // skewX by `v.x` and skewY by `v.y` degrees
+    pub fn skew(v: Vector) -> Affine {
+        Affine{ b: v.y.to_radians().tan(), c: v.x.to_radians().tan(), ..Affine::IDENTITY }
+    }
+
+    // Transform keeps its matrix private, so it's read back from where it
+    // sends the origin and the unit vectors
+    pub fn from_transform(t: Transform) -> Affine {
+        let origin = t * Vector{ x: 0.0, y: 0.0 };
+        let x = t * Vector{ x: 1.0, y: 0.0 } - origin;
+        let y = t * Vector{ x: 0.0, y: 1.0 } - origin;
+        Affine{ a: x.x, b: x.y, c: y.x, d: y.y, e: origin.x, f: origin.y }
+    }
+
+    pub fn from_usvg(t: &usvg::Transform) -> Affine {
+        Affine{
+            a: t.a as f32,
+            b: t.b as f32,
+            c: t.c as f32,
+            d: t.d as f32,
+            e: t.e as f32,
+            f: t.f as f32,
+        }
+    }
+
+    // The largest factor a unit vector can be stretched by
+    pub fn max_scale(&self) -> f32 {
+        (self.a * self.a + self.b * self.b).sqrt()
+            .max((self.c * self.c + self.d * self.d).sqrt())
+    }
+
+    // The rotation in degrees, skew and scale that compose, in that order,
+    // into this transform's linear part, the way Sprite builds them. Skews
+    // come out as skewX alone. None when it flattens everything onto a
+    // line.
+    pub fn decompose(&self) -> Option<(f32, Vector, Vector)> {
+        let sx = (self.a * self.a + self.b * self.b).sqrt();
+        let det = self.a * self.d - self.b * self.c;
+        if sx < std::f32::EPSILON || det.abs() < std::f32::EPSILON {
+            return None;
+        }
+        let (sin, cos) = (self.b / sx, self.a / sx);
+        let sy = det / sx;
+        let skew_x = ((cos * self.c + sin * self.d) / sy).atan().to_degrees();
+        Some((sin.atan2(cos).to_degrees(), Vector{ x: skew_x, y: 0.0 }, Vector{ x: sx, y: sy }))
+    }
+
+    pub fn inverse(&self) -> Option<Affine> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < std::f32::EPSILON {
+            return None;
+        }
+        Some(Affine{
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det,
+        })
+    }
+}
+
+impl Mul<Affine> for Affine {
+    type Output = Affine;
+
+    // Like quicksilver's Transform, `x * y` applies `y` first
+    fn mul(self, o: Affine) -> Affine {
+        Affine{
+            a: self.a * o.a + self.c * o.b,
+            b: self.b * o.a + self.d * o.b,
+            c: self.a * o.c + self.c * o.d,
+            d: self.b * o.c + self.d * o.d,
+            e: self.a * o.e + self.c * o.f + self.e,
+            f: self.b * o.e + self.d * o.f + self.f,
+        }
+    }
+}
+
+impl Mul<Vector> for Affine {
+    type Output = Vector;
+
+    fn mul(self, v: Vector) -> Vector {
+        Vector{
+            x: self.a * v.x + self.c * v.y + self.e,
+            y: self.b * v.x + self.d * v.y + self.f,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Affine, b: Affine) -> bool {
+        [(a.a, b.a), (a.b, b.b), (a.c, b.c), (a.d, b.d), (a.e, b.e), (a.f, b.f)].iter()
+            .all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    fn compose(rotation: f32, skew: Vector, scale: Vector) -> Affine {
+        Affine::rotate(rotation) * Affine::skew(skew) * Affine::scale(scale)
+    }
+
+    #[test]
+    fn decompose_gives_back_what_composed_it() {
+        for (rotation, skew, scale) in [
+            (0.0, 0.0, Vector{ x: 1.0, y: 1.0 }),
+            (30.0, 0.0, Vector{ x: 2.0, y: 3.0 }),
+            (-135.0, 20.0, Vector{ x: 0.5, y: 1.5 }),
+            (90.0, -40.0, Vector{ x: 1.0, y: -2.0 }),
+        ].iter() {
+            let (r, k, s) = compose(*rotation, Vector{ x: *skew, y: 0.0 }, *scale).decompose().unwrap();
+            assert!((r - rotation).abs() < 1e-3, "rotation {} for {}", r, rotation);
+            assert!((k.x - skew).abs() < 1e-3 && k.y == 0.0, "skew {:?} for {}", k, skew);
+            assert!((s.x - scale.x).abs() < 1e-4 && (s.y - scale.y).abs() < 1e-4, "scale {:?} for {:?}", s, scale);
+        }
+    }
+
+    #[test]
+    fn skew_y_comes_out_as_an_equivalent_skew_x() {
+        let t = compose(10.0, Vector{ x: 15.0, y: 25.0 }, Vector{ x: 1.5, y: 0.5 });
+        let (r, k, s) = t.decompose().unwrap();
+        assert!(close(compose(r, k, s), t));
+    }
+
+    #[test]
+    fn flat_transforms_dont_decompose() {
+        assert_eq!(Affine::scale(Vector{ x: 0.0, y: 1.0 }).decompose(), None);
+        assert_eq!(Affine{ a: 1.0, b: 1.0, c: 2.0, d: 2.0, e: 0.0, f: 0.0 }.decompose(), None);
+    }
+}
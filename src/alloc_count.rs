@@ -0,0 +1,133 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The system allocator, counting every allocation so the debug overlay can
+// show how many happen per frame. Only installed with the
+// `count-allocations` feature.
+pub struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Tests run side by side, so each counts only its own thread
+    static THREAD_ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+fn count() {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    // Gone while the thread shuts down
+    let _ = THREAD_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+// Allocations since the game started
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quicksilver::geom::{Rectangle, Vector};
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use crate::components::Spawner;
+    use crate::debug::Overlay;
+    use crate::glyph::GlyphSet;
+    use crate::input::Input;
+    use crate::profile::Profiler;
+    use crate::systems;
+    use crate::world::World;
+
+    const SIZE: Vector = Vector{ x: 1000.0, y: 1000.0 };
+
+    fn thread_allocations() -> usize {
+        THREAD_ALLOCATIONS.with(|count| count.get())
+    }
+
+    struct Game {
+        world: World,
+        glyphs: GlyphSet,
+        rng: StdRng,
+        profiler: Profiler,
+        overlay: Overlay,
+    }
+
+    // What the game runs every frame that doesn't need a window, in the
+    // same order
+    fn frame(game: &mut Game) {
+        let Game{ ref mut world, ref glyphs, ref mut rng, ref mut profiler, ref mut overlay } = *game;
+        let input = Input::none();
+        let missing = glyphs.id("missing");
+        profiler.frame();
+        profiler.begin("update");
+        systems::friction(world);
+        systems::steer(world, &input);
+        systems::lifetime(world, 1.0);
+        world.maintain();
+        systems::movement(world, 1.0);
+        systems::spin(world, 1.0);
+        systems::tweens(world, 1.0);
+        systems::animate(world, glyphs, 1.0);
+        systems::face(world);
+        systems::wrap(world);
+        systems::cursor(world, Vector{ x: 10.0, y: 10.0 });
+        systems::spawn_enemies(world, rng, SIZE, 1.0);
+        systems::flash(world, 1.0);
+        systems::shoot(world, &input, Vector{ x: 10.0, y: 10.0 }, false, missing, None, 1.0);
+        systems::transforms(world);
+        systems::collide(world, glyphs);
+        systems::events(world, missing);
+        overlay.update_text(profiler);
+        overlay.update_graph(profiler);
+        profiler.end("update");
+    }
+
+    #[test]
+    fn steady_frames_allocate_nothing() {
+        let glyphs = GlyphSet::new().unwrap();
+        let mut world = World::new();
+        let area = Rectangle::new(Vector{ x: 0.0, y: 0.0 }, SIZE);
+        systems::spawn_cursor(&mut world, glyphs.id("missing"));
+        systems::spawn_hero(&mut world, glyphs.id("missing"), None, glyphs.id("missing"), SIZE * 0.5, area);
+        let spawner = world.spawn();
+        world.spawners.insert(spawner, Spawner::new(glyphs.id("missing"), None, 1.0));
+        let mut profiler = Profiler::new();
+        profiler.record();
+        let mut game = Game{
+            world: world,
+            glyphs: glyphs,
+            rng: StdRng::seed_from_u64(44),
+            profiler: profiler,
+            overlay: Overlay::new(),
+        };
+
+        // Until the first enemies have lived out their lifetimes and every
+        // buffer has grown to fit
+        for _ in 0..1000 {
+            frame(&mut game);
+        }
+        let before = thread_allocations();
+        for _ in 0..200 {
+            frame(&mut game);
+        }
+        assert_eq!(thread_allocations() - before, 0);
+    }
+}
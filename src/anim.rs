@@ -0,0 +1,210 @@
+use crate::glyph::GlyphId;
+
+// `data-duration` is in milliseconds, the game counts frames
+const FRAME_MS: f32 = 1000.0 / 60.0;
+pub const DEFAULT_DURATION_MS: f32 = 100.0;
+
+// Where a clip goes after its last frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Loop,
+    // Back and forth, without repeating the first or last frame
+    PingPong,
+    // Stays on the last frame
+    Once,
+}
+
+pub struct Frame {
+    pub glyph: GlyphId,
+    // In frames, at least one
+    pub duration: f32,
+    // Sent to the game's events when the frame is shown
+    pub event: Option<&'static str>,
+}
+
+impl Frame {
+    pub fn new(glyph: GlyphId, duration_ms: f32, event: Option<&'static str>) -> Frame {
+        Frame{ glyph: glyph, duration: (duration_ms / FRAME_MS).max(1.0), event: event }
+    }
+}
+
+// The frames of a `glyph_<name>@<n>` series, in order of `n`
+pub struct Clip {
+    pub frames: Vec<Frame>,
+}
+
+// Handed out by GlyphSet::clip, and kept when the sheet is reloaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClipId(pub usize);
+
+// A sprite playing a clip
+pub struct Animation {
+    pub clip: ClipId,
+    pub mode: Mode,
+    // 1 plays at the durations from the SVG
+    pub speed: f32,
+    frame: usize,
+    elapsed: f32,
+    forward: bool,
+    started: bool,
+    done: bool,
+}
+
+impl Animation {
+    pub fn new(clip: ClipId, mode: Mode) -> Animation {
+        Animation{
+            clip: clip,
+            mode: mode,
+            speed: 1.0,
+            frame: 0,
+            elapsed: 0.0,
+            forward: true,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn next(&mut self, len: usize) -> Option<usize> {
+        let last = len - 1;
+        match self.mode {
+            Mode::Loop => Some((self.frame + 1) % len),
+            Mode::Once if self.frame >= last => None,
+            Mode::Once => Some(self.frame + 1),
+            Mode::PingPong if len == 1 => Some(0),
+            Mode::PingPong => {
+                if self.forward && self.frame >= last {
+                    self.forward = false;
+                } else if !self.forward && self.frame == 0 {
+                    self.forward = true;
+                }
+                Some(if self.forward { self.frame + 1 } else { self.frame - 1 })
+            },
+        }
+    }
+
+    // Moves `dt` frames on and returns the glyph to draw
+    pub fn step(&mut self, clip: &Clip, dt: f32, emit: &mut dyn FnMut(&'static str)) -> Option<GlyphId> {
+        if clip.frames.is_empty() {
+            return None;
+        }
+        // The sheet may have been reloaded with fewer frames
+        self.frame = self.frame.min(clip.frames.len() - 1);
+        if !self.started {
+            self.started = true;
+            if let Some(event) = clip.frames[self.frame].event {
+                emit(event);
+            }
+        }
+        if !self.done {
+            self.elapsed += dt * self.speed;
+            while self.elapsed >= clip.frames[self.frame].duration {
+                self.elapsed -= clip.frames[self.frame].duration;
+                match self.next(clip.frames.len()) {
+                    Some(frame) => {
+                        self.frame = frame;
+                        if let Some(event) = clip.frames[frame].event {
+                            emit(event);
+                        }
+                    },
+                    None => {
+                        self.done = true;
+                        break;
+                    },
+                }
+            }
+        }
+        Some(clip.frames[self.frame].glyph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::graphics::Mesh;
+
+    use super::*;
+    use crate::glyph::{Glyph, GlyphSet};
+
+    // A clip of `len` frames lasting a game frame each, and the glyph of
+    // each frame
+    fn clip(len: usize, events: &[Option<&'static str>]) -> (Clip, Vec<GlyphId>) {
+        let mut glyphs = GlyphSet::new().unwrap();
+        let ids: Vec<GlyphId> = (0..len)
+            .map(|n| glyphs.insert(&n.to_string(), Glyph::new(n.to_string(), Mesh::new())))
+            .collect();
+        let frames = ids.iter().enumerate()
+            .map(|(n, id)| Frame::new(*id, FRAME_MS, events.get(n).cloned().unwrap_or(None)))
+            .collect();
+        (Clip{ frames: frames }, ids)
+    }
+
+    // The frame shown on each of `steps` game frames
+    fn play(len: usize, mode: Mode, steps: usize) -> Vec<usize> {
+        let (clip, ids) = clip(len, &[]);
+        let mut animation = Animation::new(ClipId(0), mode);
+        (0..steps)
+            .map(|_| {
+                let glyph = animation.step(&clip, 1.0, &mut |_| ()).unwrap();
+                ids.iter().position(|id| *id == glyph).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        assert_eq!(play(1, Mode::Loop, 4), vec![0, 0, 0, 0]);
+        assert_eq!(play(2, Mode::Loop, 4), vec![1, 0, 1, 0]);
+        assert_eq!(play(3, Mode::Loop, 6), vec![1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn ping_pong_turns_at_the_ends() {
+        assert_eq!(play(1, Mode::PingPong, 4), vec![0, 0, 0, 0]);
+        assert_eq!(play(2, Mode::PingPong, 4), vec![1, 0, 1, 0]);
+        assert_eq!(play(3, Mode::PingPong, 8), vec![1, 2, 1, 0, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn once_stays_on_the_last_frame() {
+        assert_eq!(play(1, Mode::Once, 3), vec![0, 0, 0]);
+        assert_eq!(play(2, Mode::Once, 3), vec![1, 1, 1]);
+        assert_eq!(play(3, Mode::Once, 4), vec![1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn durations_are_at_least_a_frame() {
+        assert_eq!(Frame::new(GlyphSet::new().unwrap().id("missing"), 0.0, None).duration, 1.0);
+        assert_eq!(Frame::new(GlyphSet::new().unwrap().id("missing"), FRAME_MS * 3.0, None).duration, 3.0);
+    }
+
+    #[test]
+    fn events_are_sent_as_frames_are_shown() {
+        let (clip, _) = clip(3, &[Some("start"), None, Some("end")]);
+        let mut animation = Animation::new(ClipId(0), Mode::Loop);
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            animation.step(&clip, 1.0, &mut |name| events.push(name));
+        }
+        // The first frame's event is sent when it starts, not only on looping
+        assert_eq!(events, vec!["start", "end", "start"]);
+    }
+
+    #[test]
+    fn events_are_sent_for_skipped_frames() {
+        let (clip, _) = clip(3, &[Some("a"), Some("b"), Some("c")]);
+        let mut animation = Animation::new(ClipId(0), Mode::Once);
+        let mut events = Vec::new();
+        animation.step(&clip, 10.0, &mut |name| events.push(name));
+        assert_eq!(events, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn reloaded_clips_with_fewer_frames_are_clamped() {
+        let (long, _) = clip(3, &[]);
+        let (short, ids) = clip(1, &[]);
+        let mut animation = Animation::new(ClipId(0), Mode::PingPong);
+        animation.step(&long, 2.0, &mut |_| ());
+        assert_eq!(animation.step(&short, 0.0, &mut |_| ()), Some(ids[0]));
+        assert_eq!(animation.step(&short, 1.0, &mut |_| ()), Some(ids[0]));
+        assert_eq!(animation.step(&Clip{ frames: Vec::new() }, 1.0, &mut |_| ()), None);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use quicksilver::{
+    geom::Rectangle,
+    graphics::Color,
+    lifecycle::Window,
+};
+
+use crate::clock;
+use crate::png;
+
+// Recordings keep every third frame, 20 a second at 60fps
+const FRAME_STEP: usize = 3;
+// About 5 seconds, a recording stops growing after this
+const MAX_FRAMES: usize = 100;
+
+// A captured frame, 8 bit RGBA rows from the top
+pub struct Frame {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Frame {
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.rgba)
+    }
+
+    // Each 2x2 block averaged into a pixel, which keeps recordings
+    // shareable. An odd last row or column is dropped.
+    fn halved(&self) -> Frame {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                for c in 0..4 {
+                    let at = |dx: usize, dy: usize|
+                        self.rgba[((y * 2 + dy) * self.width as usize + x * 2 + dx) * 4 + c] as u32;
+                    rgba.push(((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1) + 2) / 4) as u8);
+                }
+            }
+        }
+        Frame{ width: width, height: height, rgba: rgba }
+    }
+}
+
+// Flushes what's been drawn so far and reads it back from the framebuffer,
+// at the size the window is. The view and background are already in there.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn grab(window: &mut Window, _view: Rectangle, _background: Color) -> Result<Frame, String> {
+    window.flush().map_err(|err| format!("{:?}", err))?;
+    let image = window.screenshot(quicksilver::graphics::PixelFormat::RGBA).to_rgba();
+    let (width, height) = (image.width(), image.height());
+    let mut rgba = image.into_raw();
+    // Blending leaves the framebuffer's alpha below 1 where there are
+    // translucent shapes, though it's shown opaque
+    for pixel in rgba.chunks_mut(4) {
+        pixel[3] = 255;
+    }
+    Ok(Frame{ width: width, height: height, rgba: rgba })
+}
+
+// The canvas is cleared once it's shown, so `view` of the frame's mesh is
+// drawn on the CPU instead, at the size of the canvas
+#[cfg(target_arch = "wasm32")]
+pub fn grab(window: &mut Window, view: Rectangle, background: Color) -> Result<Frame, String> {
+    let size = window.screen_size();
+    let (width, height) = (size.x.round() as u32, size.y.round() as u32);
+    let mut raster = crate::raster::Raster::new(width, height, view);
+    raster.clear(background);
+    raster.draw(window.mesh());
+    Ok(Frame{ width: width, height: height, rgba: raster.to_rgba() })
+}
+
+// `kind-YYYYMMDD-HHMMSS-seedN.png`, so a capture can be matched to a replay
+pub fn file_name(kind: &str, seed: u64) -> String {
+    format!("{}-{}-seed{}.png", kind, clock::timestamp(), seed)
+}
+
+// Collects frames while a key is held and turns them into an APNG once it
+// is let go, a frame at a time so the game doesn't stall
+pub struct Recording {
+    // Halved as they're recorded, all the size of the first
+    frames: VecDeque<Frame>,
+    seen: usize,
+    // Once recording stops, while the frames are encoded
+    encoding: Option<png::Animation>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording{ frames: VecDeque::new(), seen: 0, encoding: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Counts a frame while the key is held, returning whether it should be
+    // grabbed and recorded. Never while an earlier recording is still being
+    // encoded.
+    pub fn tick(&mut self) -> bool {
+        if self.encoding.is_some() {
+            return false;
+        }
+        self.seen += 1;
+        (self.seen - 1) % FRAME_STEP == 0 && self.frames.len() < MAX_FRAMES
+    }
+
+    // Frames of another size, from resizing the window part way through,
+    // are left out
+    pub fn record(&mut self, frame: &Frame) {
+        let frame = frame.halved();
+        match self.frames.front() {
+            Some(first) if (first.width, first.height) != (frame.width, frame.height) => {},
+            _ => self.frames.push_back(frame),
+        }
+    }
+
+    // Encodes the next frame, returning the APNG after the last one and
+    // starting over
+    pub fn encode(&mut self) -> Option<Vec<u8>> {
+        let frames = self.frames.len() as u32;
+        let (width, height) = self.frames.front().map(|f| (f.width, f.height)).unwrap_or((0, 0));
+        let animation = self.encoding.get_or_insert_with(|| {
+            png::Animation::new(width, height, frames, (FRAME_STEP as u16, 60))
+        });
+        if let Some(frame) = self.frames.pop_front() {
+            animation.frame(&frame.rgba);
+        }
+        if !self.frames.is_empty() {
+            return None;
+        }
+        self.seen = 0;
+        self.encoding.take().map(|animation| animation.finish())
+    }
+}
+
+// Writes next to where the game was started
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(name: &str, bytes: &[u8]) -> Result<(), String> {
+    std::fs::write(name, bytes).map_err(|err| err.to_string())
+}
+
+// Offers the file as a download
+#[cfg(target_arch = "wasm32")]
+pub fn save(name: &str, bytes: &[u8]) -> Result<(), String> {
+    let data = unsafe { stdweb::UnsafeTypedArray::new(bytes) };
+    js! { @(no_return)
+        var blob = new Blob([@{data}], { type: "image/png" });
+        var link = document.createElement("a");
+        link.href = URL.createObjectURL(blob);
+        link.download = @{name};
+        document.body.appendChild(link);
+        link.click();
+        document.body.removeChild(link);
+        setTimeout(function() { URL.revokeObjectURL(link.href); }, 1000);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, value: u8) -> Frame {
+        Frame{ width: width, height: height, rgba: vec![value; (width * height * 4) as usize] }
+    }
+
+    #[test]
+    fn halving_averages_blocks_and_drops_odd_edges() {
+        let mut rgba = vec![0; 5 * 3 * 4];
+        // The top left block is two white pixels and two black
+        for i in 0..4 {
+            rgba[i] = 255;
+            rgba[5 * 4 + i] = 255;
+        }
+        let half = Frame{ width: 5, height: 3, rgba: rgba }.halved();
+        assert_eq!((half.width, half.height), (2, 1));
+        assert_eq!(&half.rgba, &[128, 128, 128, 128, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn every_few_frames_are_recorded() {
+        let mut recording = Recording::new();
+        let ticks: Vec<bool> = (0..FRAME_STEP * 2).map(|_| recording.tick()).collect();
+        let mut expected = vec![false; FRAME_STEP * 2];
+        expected[0] = true;
+        expected[FRAME_STEP] = true;
+        assert_eq!(ticks, expected);
+    }
+
+    #[test]
+    fn frames_of_another_size_are_left_out() {
+        let mut recording = Recording::new();
+        recording.record(&frame(4, 4, 0));
+        recording.record(&frame(8, 8, 0));
+        recording.record(&frame(4, 4, 0));
+        assert_eq!(recording.frames.len(), 2);
+    }
+
+    #[test]
+    fn recordings_are_encoded_a_frame_at_a_time() {
+        let mut recording = Recording::new();
+        for _ in 0..3 {
+            assert!(recording.tick());
+            recording.record(&frame(4, 4, 255));
+            for _ in 1..FRAME_STEP {
+                recording.tick();
+            }
+        }
+        assert_eq!(recording.encode(), None);
+        // Frames aren't grabbed while encoding
+        assert!(!recording.tick());
+        assert_eq!(recording.encode(), None);
+        let apng = recording.encode().unwrap();
+        assert!(recording.is_empty());
+        // acTL, after the signature and IHDR, counts the frames
+        assert_eq!(&apng[37..41], b"acTL");
+        assert_eq!(&apng[41..45], &3u32.to_be_bytes());
+        // IHDR has the halved size
+        assert_eq!(&apng[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+    }
+}
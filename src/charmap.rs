@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+// What to try, in order, for a character without a glyph of its own.
+// Each step also applies to the characters produced by earlier steps, so
+// 'É' can become 'E' and then 'e'.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fallback {
+    StripAccent,
+    Uppercase,
+    Lowercase,
+    Glyph(String),
+}
+
+pub fn default_fallbacks() -> Vec<Fallback> {
+    vec![Fallback::StripAccent, Fallback::Uppercase, Fallback::Lowercase]
+}
+
+// Accented Latin-1 and Latin Extended-A letters by their base letter
+const ACCENTS: [(char, &str); 24] = [
+    ('A', "ÀÁÂÃÄÅĀĂĄ"),
+    ('a', "àáâãäåāăą"),
+    ('C', "ÇĆĈĊČ"),
+    ('c', "çćĉċč"),
+    ('D', "ĎĐ"),
+    ('d', "ďđ"),
+    ('E', "ÈÉÊËĒĔĖĘĚ"),
+    ('e', "èéêëēĕėęě"),
+    ('G', "ĜĞĠĢ"),
+    ('g', "ĝğġģ"),
+    ('I', "ÌÍÎÏĨĪĬĮİ"),
+    ('i', "ìíîïĩīĭįı"),
+    ('L', "ĹĻĽĿŁ"),
+    ('l', "ĺļľŀł"),
+    ('N', "ÑŃŅŇ"),
+    ('n', "ñńņň"),
+    ('O', "ÒÓÔÕÖØŌŎŐ"),
+    ('o', "òóôõöøōŏő"),
+    ('S', "ŚŜŞŠ"),
+    ('s', "śŝşš"),
+    ('U', "ÙÚÛÜŨŪŬŮŰŲ"),
+    ('u', "ùúûüũūŭůűų"),
+    ('Y', "ÝŶŸ"),
+    ('y', "ýÿŷ"),
+];
+
+pub fn strip_accent(c: char) -> Option<char> {
+    if c.is_ascii() {
+        return None;
+    }
+    ACCENTS.iter()
+        .find(|(_, accented)| accented.contains(c))
+        .map(|(base, _)| *base)
+        .or_else(|| match c {
+            'Ź' | 'Ż' | 'Ž' => Some('Z'),
+            'ź' | 'ż' | 'ž' => Some('z'),
+            'Ŕ' | 'Ŗ' | 'Ř' => Some('R'),
+            'ŕ' | 'ŗ' | 'ř' => Some('r'),
+            'Ţ' | 'Ť' => Some('T'),
+            'ţ' | 'ť' => Some('t'),
+            _ => None,
+        })
+}
+
+fn single_char(chars: impl Iterator<Item = char>) -> Option<char> {
+    let mut chars = chars;
+    let c = chars.next()?;
+    if chars.next().is_some() { None } else { Some(c) }
+}
+
+// The characters to look for, in order, when drawing `c`
+pub fn candidates(c: char, fallbacks: &[Fallback]) -> Vec<char> {
+    let mut candidates = vec![c];
+    for fallback in fallbacks {
+        let mut added = Vec::new();
+        for candidate in candidates.iter() {
+            let next = match fallback {
+                Fallback::StripAccent => strip_accent(*candidate),
+                Fallback::Uppercase => single_char(candidate.to_uppercase()),
+                Fallback::Lowercase => single_char(candidate.to_lowercase()),
+                Fallback::Glyph(_) => None,
+            };
+            if let Some(next) = next {
+                if !candidates.contains(&next) && !added.contains(&next) {
+                    added.push(next);
+                }
+            }
+        }
+        candidates.extend(added);
+    }
+    candidates
+}
+
+pub struct CharMap {
+    chars: HashMap<char, String>,
+    pub fallbacks: Vec<Fallback>,
+}
+
+impl CharMap {
+    pub fn new() -> CharMap {
+        CharMap{ chars: HashMap::new(), fallbacks: default_fallbacks() }
+    }
+
+    pub fn get(&self, c: char) -> Option<&str> {
+        self.chars.get(&c).map(|name| name.as_str())
+    }
+
+    pub fn insert(&mut self, c: char, name: &str) {
+        self.chars.insert(c, name.to_owned());
+    }
+
+    // Glyphs named after a single character draw it, unless a glyph claims
+    // that character explicitly
+    pub fn insert_implicit(&mut self, name: &str) {
+        if let Some(c) = single_char(name.chars()) {
+            self.chars.entry(c).or_insert_with(|| name.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accented_capitals_try_the_base_letter_then_lower_case() {
+        assert_eq!(candidates('É', &default_fallbacks()), vec!['É', 'E', 'é', 'e']);
+        assert_eq!(candidates('ø', &default_fallbacks()), vec!['ø', 'o', 'Ø', 'O']);
+        assert_eq!(candidates('Ž', &default_fallbacks()), vec!['Ž', 'Z', 'ž', 'z']);
+    }
+
+    #[test]
+    fn plain_letters_try_the_other_case() {
+        assert_eq!(candidates('a', &default_fallbacks()), vec!['a', 'A']);
+        assert_eq!(candidates('Q', &default_fallbacks()), vec!['Q', 'q']);
+        assert_eq!(candidates('7', &default_fallbacks()), vec!['7']);
+    }
+
+    #[test]
+    fn case_changes_to_several_characters_are_skipped() {
+        // 'ß' upper cases to "SS" and 'İ' lower cases to 'i' and a dot
+        assert_eq!(candidates('ß', &default_fallbacks()), vec!['ß']);
+        assert_eq!(candidates('İ', &default_fallbacks()), vec!['İ', 'I', 'i']);
+    }
+
+    #[test]
+    fn fallbacks_apply_in_their_order() {
+        let fallbacks = [Fallback::Lowercase, Fallback::StripAccent];
+        assert_eq!(candidates('É', &fallbacks), vec!['É', 'é', 'E', 'e']);
+        assert_eq!(candidates('É', &[]), vec!['É']);
+        assert_eq!(candidates('É', &[Fallback::Glyph("missing".to_owned())]), vec!['É']);
+    }
+
+    #[test]
+    fn only_accented_letters_are_stripped() {
+        assert_eq!(strip_accent('a'), None);
+        assert_eq!(strip_accent('Å'), Some('A'));
+        assert_eq!(strip_accent('ť'), Some('t'));
+        assert_eq!(strip_accent('☺'), None);
+    }
+
+    #[test]
+    fn explicit_characters_win_over_glyph_names() {
+        let mut chars = CharMap::new();
+        chars.insert_implicit("a");
+        chars.insert_implicit("hero");
+        assert_eq!(chars.get('a'), Some("a"));
+        assert_eq!(chars.get('h'), None);
+        chars.insert('b', "bee");
+        chars.insert_implicit("b");
+        assert_eq!(chars.get('b'), Some("bee"));
+    }
+}
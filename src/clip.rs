@@ -0,0 +1,195 @@
+use quicksilver::{
+    geom::Vector,
+    graphics::{Color, GpuTriangle, Mesh, Vertex},
+};
+
+// Clipping of tessellated meshes against convex polygons (in practice
+// the rectangles Inkscape writes out for clip paths and masks)
+
+fn lerp(a: &(Vector, Color), b: &(Vector, Color), t: f32) -> (Vector, Color) {
+    (
+        a.0 + (b.0 - a.0) * t,
+        Color{
+            r: a.1.r + (b.1.r - a.1.r) * t,
+            g: a.1.g + (b.1.g - a.1.g) * t,
+            b: a.1.b + (b.1.b - a.1.b) * t,
+            a: a.1.a + (b.1.a - a.1.a) * t,
+        }
+    )
+}
+
+fn signed_area(polygon: &[Vector]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..polygon.len() {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    area / 2.0
+}
+
+// Returns the polygon if it is convex and not degenerate
+pub fn convex(polygon: Vec<Vector>) -> Option<Vec<Vector>> {
+    if polygon.len() < 3 {
+        return None;
+    }
+    let area = signed_area(&polygon);
+    if area.abs() < std::f32::EPSILON {
+        return None;
+    }
+    for i in 0..polygon.len() {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        let c = polygon[(i + 2) % polygon.len()];
+        if (b - a).cross(c - b) * area < 0.0 {
+            return None;
+        }
+    }
+    Some(polygon)
+}
+
+// Sutherland-Hodgman, interpolating vertex colours along cut edges
+fn clip_polygon(mut subject: Vec<(Vector, Color)>, clip: &[Vector]) -> Vec<(Vector, Color)> {
+    let orientation = signed_area(clip).signum();
+    for i in 0..clip.len() {
+        if subject.is_empty() {
+            break;
+        }
+        let a = clip[i];
+        let edge = clip[(i + 1) % clip.len()] - a;
+        let side = |p: Vector| edge.cross(p - a) * orientation;
+
+        let input = subject;
+        subject = Vec::with_capacity(input.len() + 2);
+        for j in 0..input.len() {
+            let current = &input[j];
+            let next = &input[(j + 1) % input.len()];
+            let current_side = side(current.0);
+            let next_side = side(next.0);
+            if current_side >= 0.0 {
+                subject.push(*current);
+            }
+            if (current_side >= 0.0) != (next_side >= 0.0) {
+                subject.push(lerp(current, next, current_side / (current_side - next_side)));
+            }
+        }
+    }
+    subject
+}
+
+// Appends the parts of `src` inside every one of `clips` to `dest`
+pub fn clip_mesh(src: &Mesh, clips: &[Vec<Vector>], dest: &mut Mesh) {
+    for t in src.triangles.iter() {
+        let mut polygon: Vec<(Vector, Color)> = t.indices.iter()
+            .map(|i| (src.vertices[*i as usize].pos, src.vertices[*i as usize].col))
+            .collect();
+        for clip in clips {
+            polygon = clip_polygon(polygon, clip);
+        }
+        if polygon.len() < 3 {
+            continue;
+        }
+
+        let offset = dest.vertices.len() as u32;
+        dest.vertices.extend(polygon.iter()
+            .map(|(pos, col)| Vertex{ pos: *pos, col: *col, tex_pos: None }));
+        for i in 1..(polygon.len() as u32 - 1) {
+            dest.triangles.push(GpuTriangle{
+                z: t.z,
+                indices: [offset, offset + i, offset + i + 1],
+                image: t.image.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32) -> Vector {
+        Vector{ x: x, y: y }
+    }
+
+    // 0 to 10 on both axes
+    fn square() -> Vec<Vector> {
+        vec![v(0.0, 0.0), v(10.0, 0.0), v(10.0, 10.0), v(0.0, 10.0)]
+    }
+
+    fn triangle(points: [Vector; 3], color: Color) -> Vec<(Vector, Color)> {
+        points.iter().map(|p| (*p, color)).collect()
+    }
+
+    fn area(polygon: &[(Vector, Color)]) -> f32 {
+        let points: Vec<Vector> = polygon.iter().map(|p| p.0).collect();
+        signed_area(&points).abs()
+    }
+
+    #[test]
+    fn inside_is_kept_whole() {
+        let subject = triangle([v(1.0, 1.0), v(9.0, 1.0), v(1.0, 9.0)], Color::RED);
+        let clipped = clip_polygon(subject.clone(), &square());
+        assert_eq!(clipped.len(), 3);
+        for (a, b) in clipped.iter().zip(subject.iter()) {
+            assert_eq!(a.0, b.0);
+        }
+    }
+
+    #[test]
+    fn outside_is_dropped() {
+        let subject = triangle([v(11.0, 1.0), v(19.0, 1.0), v(11.0, 9.0)], Color::RED);
+        assert!(clip_polygon(subject, &square()).is_empty());
+    }
+
+    #[test]
+    fn straddling_is_cut_at_the_edges() {
+        let mut subject = triangle([v(5.0, 5.0), v(15.0, 5.0), v(5.0, 15.0)], Color::BLUE);
+        subject[0].1 = Color::RED;
+        let clipped = clip_polygon(subject, &square());
+        assert!((area(&clipped) - 25.0).abs() < 1e-3);
+        for (p, _) in clipped.iter() {
+            assert!(p.x >= 0.0 && p.x <= 10.0 && p.y >= 0.0 && p.y <= 10.0);
+        }
+        // Halfway along the cut edge, halfway between the colours
+        let cut = clipped.iter().find(|(p, _)| *p == v(10.0, 5.0)).unwrap();
+        assert!((cut.1.r - 0.5).abs() < 1e-4 && (cut.1.b - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn clip_winding_does_not_matter() {
+        let mut clip = square();
+        clip.reverse();
+        let subject = triangle([v(5.0, 5.0), v(15.0, 5.0), v(5.0, 15.0)], Color::RED);
+        assert!((area(&clip_polygon(subject, &clip)) - 25.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn only_convex_polygons_are_clips() {
+        assert!(convex(square()).is_some());
+        assert!(convex(vec![v(0.0, 0.0), v(10.0, 0.0)]).is_none());
+        assert!(convex(vec![v(0.0, 0.0), v(5.0, 0.0), v(10.0, 0.0)]).is_none());
+        let arrow = vec![v(0.0, 0.0), v(10.0, 0.0), v(10.0, 10.0), v(5.0, 2.0), v(0.0, 10.0)];
+        assert!(convex(arrow).is_none());
+    }
+
+    #[test]
+    fn meshes_are_refanned_from_the_cut_polygons() {
+        let mut src = Mesh::new();
+        for p in [v(5.0, 5.0), v(15.0, 5.0), v(5.0, 15.0)].iter() {
+            src.vertices.push(Vertex{ pos: *p, col: Color::WHITE, tex_pos: None });
+        }
+        src.triangles.push(GpuTriangle{ z: 2.0, indices: [0, 1, 2], image: None });
+        let mut dest = Mesh::new();
+        clip_mesh(&src, &[square()], &mut dest);
+        // The 5 to 10 square, fanned from one corner
+        let area: f32 = dest.triangles.iter()
+            .map(|t| signed_area(&[
+                dest.vertices[t.indices[0] as usize].pos,
+                dest.vertices[t.indices[1] as usize].pos,
+                dest.vertices[t.indices[2] as usize].pos,
+            ]).abs())
+            .sum();
+        assert!((area - 25.0).abs() < 1e-3);
+        assert!(dest.triangles.iter().all(|t| t.z == 2.0));
+    }
+}
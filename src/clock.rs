@@ -0,0 +1,51 @@
+// Milliseconds since the first call, for timing frames.
+// std::time::Instant panics on wasm32-unknown-unknown, so the browser's
+// clock is used there instead.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now() -> f64 {
+    use std::time::Instant;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+    }
+    START.with(|start| {
+        let elapsed = start.elapsed();
+        elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1_000_000.0
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now() -> f64 {
+    stdweb::web::Date::now()
+}
+
+// Seconds since 1970 by the wall clock
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn unix_time() -> u64 {
+    (stdweb::web::Date::now() / 1000.0) as u64
+}
+
+// The current UTC date and time as `YYYYMMDD-HHMMSS`, for file names
+pub fn timestamp() -> String {
+    let secs = unix_time();
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // Howard Hinnant's days_from_civil in reverse
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
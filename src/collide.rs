@@ -0,0 +1,27 @@
+use quicksilver::geom::Vector;
+
+// Projects `points` onto `axis`, giving the (min, max) they span along it
+fn project(points: &[Vector], axis: Vector) -> (f32, f32) {
+    points.iter().fold((std::f32::INFINITY, std::f32::NEG_INFINITY), |(min, max), p| {
+        let d = p.dot(axis);
+        (min.min(d), max.max(d))
+    })
+}
+
+// Whether two convex polygons overlap, by the separating axis theorem:
+// they don't exactly when some edge normal has their projections apart.
+// Touching counts as overlapping.
+pub fn overlaps(a: &[Vector], b: &[Vector]) -> bool {
+    for points in [a, b].iter() {
+        for i in 0..points.len() {
+            let edge = points[(i + 1) % points.len()] - points[i];
+            let axis = Vector{ x: -edge.y, y: edge.x };
+            let (a_min, a_max) = project(a, axis);
+            let (b_min, b_max) = project(b, axis);
+            if a_max < b_min || b_max < a_min {
+                return false;
+            }
+        }
+    }
+    true
+}
@@ -0,0 +1,87 @@
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::Color,
+};
+
+use crate::affine::Affine;
+use crate::anim::ClipId;
+use crate::glyph::{Glyph, GlyphId};
+use crate::world::Entity;
+
+// The player, who shrinks a little with every coin they shoot
+pub struct Hero {
+    // Frames until the next shot
+    pub cooldown: f32,
+}
+
+// Follows the mouse and hides once it stops moving
+pub struct Cursor {
+    pub last_pos: Vector,
+    // Frames left before hiding
+    pub idle: usize,
+}
+
+// Draws the sprite flat in `color` for a few frames
+pub struct Flash {
+    pub color: Color,
+    pub frames: f32,
+}
+
+// Sends enemies in from the edges of the screen
+pub struct Spawner {
+    pub glyph: GlyphId,
+    // Played once by each enemy as it arrives
+    pub clip: Option<ClipId>,
+    // Frames between waves
+    pub period: f32,
+    pub cooldown: f32,
+}
+
+impl Spawner {
+    pub fn new(glyph: GlyphId, clip: Option<ClipId>, period: f32) -> Spawner {
+        Spawner{ glyph: glyph, clip: clip, period: period, cooldown: 0.0 }
+    }
+}
+
+// Attaches an entity to another. Its position, rotation, scale and z are
+// then relative to the parent's.
+pub struct Parent {
+    pub entity: Entity,
+    // Despawn along with the parent, rather than being left where it was
+    pub cascade: bool,
+}
+
+// Where an entity ends up on screen once its parents are applied,
+// resolved every frame by systems::transforms
+#[derive(Clone, Copy)]
+pub struct WorldTransform {
+    // From glyph space to the screen
+    pub transform: Affine,
+    // The entity's position on screen
+    pub pos: Vector,
+    pub z: f32,
+}
+
+impl WorldTransform {
+    // The glyph's bounds as drawn, which is no longer a Rectangle once
+    // rotated or skewed. Clockwise from the top left.
+    pub fn corners(&self, glyph: &Glyph) -> [Vector; 4] {
+        let b = glyph.bounds;
+        [
+            self.transform * b.pos,
+            self.transform * (b.pos + Vector{ x: b.size.x, y: 0.0 }),
+            self.transform * (b.pos + b.size),
+            self.transform * (b.pos + Vector{ x: 0.0, y: b.size.y }),
+        ]
+    }
+
+    // The smallest Rectangle around the corners
+    pub fn bounds(&self, glyph: &Glyph) -> Rectangle {
+        let corners = self.corners(glyph);
+        let (min, max) = corners.iter().skip(1).fold((corners[0], corners[0]), |(min, max), c| (
+            Vector{ x: min.x.min(c.x), y: min.y.min(c.y) },
+            Vector{ x: max.x.max(c.x), y: max.y.max(c.y) },
+        ));
+        Rectangle::new(min, max - min)
+    }
+}
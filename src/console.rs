@@ -0,0 +1,279 @@
+use quicksilver::{
+    geom::{Rectangle, Transform, Vector},
+    graphics::{Background::Col, Color},
+    input::{ButtonState, Key},
+    lifecycle::{Event, Window},
+};
+
+use crate::glyph::GlyphSet;
+use crate::hud::{HUD_Z, Label};
+use crate::markup::parse_color;
+use crate::text::TextLayout;
+use crate::tween::{Ease, Property};
+
+const TEXT_SIZE: f32 = 16.0;
+// Lines of output kept above the prompt
+const HISTORY: usize = 8;
+const BG_COLOR: Color = Color{ r: 0.0, g: 0.0, b: 0.0, a: 0.7 };
+const PROMPT: &str = "> ";
+// Length of a tween typed without one
+const TWEEN_FRAMES: f32 = 60.0;
+
+pub enum Command {
+    // Glyph name and how many
+    Spawn(String, usize),
+    God,
+    // Toggles the shields demo around the hero
+    Shields,
+    TimeScale(f32),
+    Seed(u64),
+    // Tweens the hero to a value over a number of frames
+    Tween(Property, f32, Ease),
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value.parse().ok().filter(|v: &f32| v.is_finite())
+}
+
+// `x,y` or a single number for both
+fn parse_vector(value: &str) -> Option<Vector> {
+    let mut parts = value.splitn(2, ',');
+    let x = parse_number(parts.next()?)?;
+    let y = match parts.next() {
+        Some(y) => parse_number(y)?,
+        None => x,
+    };
+    Some(Vector{ x: x, y: y })
+}
+
+fn parse_property(name: &str, value: &str) -> Result<Property, String> {
+    let property = match name {
+        "position" => parse_vector(value).map(Property::Position),
+        "scale" => parse_vector(value).map(Property::Scale),
+        "rotation" => parse_number(value).map(Property::Rotation),
+        "tint" => parse_color(value).map(Property::Tint),
+        "alpha" => parse_number(value).map(Property::Alpha),
+        "z" => parse_number(value).map(Property::Z),
+        "morph" => parse_number(value).map(Property::Morph),
+        _ => return Err(format!("tween: unknown property {}", name)),
+    };
+    property.ok_or_else(|| format!("tween: bad {} {}", name, value))
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let arg = |i: usize| words.get(i).cloned().ok_or_else(|| format!("{}: missing argument", words[0]));
+    match words.first().cloned() {
+        Some("spawn") => {
+            let count = match words.get(2) {
+                Some(count) => count.parse().map_err(|_| format!("spawn: bad count {}", count))?,
+                None => 1,
+            };
+            Ok(Command::Spawn(arg(1)?.to_owned(), count))
+        },
+        Some("god") => Ok(Command::God),
+        Some("shields") => Ok(Command::Shields),
+        Some("timescale") => parse_number(arg(1)?).filter(|t| *t > 0.0).map(Command::TimeScale)
+            .ok_or_else(|| "timescale: expected a number above 0".to_owned()),
+        Some("seed") => arg(1)?.parse().map(Command::Seed)
+            .map_err(|_| "seed: expected a whole number".to_owned()),
+        Some("tween") => {
+            let property = parse_property(arg(1)?, arg(2)?)?;
+            let frames = match words.get(3) {
+                Some(frames) => parse_number(frames).filter(|f| *f > 0.0)
+                    .ok_or_else(|| format!("tween: bad frames {}", frames))?,
+                None => TWEEN_FRAMES,
+            };
+            let ease = match words.get(4) {
+                Some(ease) => Ease::from_name(ease).ok_or_else(|| format!("tween: unknown ease {}", ease))?,
+                None => Ease::Linear,
+            };
+            Ok(Command::Tween(property, frames, ease))
+        },
+        Some(other) => Err(format!("unknown command {}", other)),
+        None => Err("commands: spawn <glyph> [n], god, shields, timescale <f>, seed <n>, \
+                     tween <property> <value> [frames] [ease]".to_owned()),
+    }
+}
+
+// A drop-down console toggled with the key left of 1
+pub struct Console {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    label: Label,
+    width: f32,
+    // Set when the text needs building again
+    dirty: bool,
+}
+
+impl Console {
+    pub fn new(width: f32) -> Console {
+        let mut label = Label::new(TextLayout::new(TEXT_SIZE), Vector{ x: 10.0, y: 10.0 });
+        label.visible = false;
+        Console{
+            open: false,
+            input: String::new(),
+            history: Vec::new(),
+            label: label,
+            width: width,
+            dirty: true,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.label.invalidate();
+        self.dirty = true;
+    }
+
+    pub fn print(&mut self, line: &str) {
+        self.history.push(line.to_owned());
+        if self.history.len() > HISTORY {
+            self.history.remove(0);
+        }
+        self.dirty = true;
+    }
+
+    // Takes keyboard events while open, returning a command once one is
+    // entered
+    pub fn event(&mut self, event: &Event) -> Option<Command> {
+        let open = self.open;
+        let input = self.input.len();
+        let command = self.handle(event);
+        if self.open != open || self.input.len() != input {
+            self.dirty = true;
+        }
+        command
+    }
+
+    fn handle(&mut self, event: &Event) -> Option<Command> {
+        match event {
+            Event::Key(Key::Grave, ButtonState::Pressed) => {
+                self.open = !self.open;
+            },
+            Event::Key(Key::Back, ButtonState::Pressed) if self.open => {
+                self.input.pop();
+            },
+            Event::Key(Key::Return, ButtonState::Pressed) if self.open => {
+                let line = self.input.split_off(0);
+                self.print(&format!("{}{}", PROMPT, line));
+                match parse(&line) {
+                    Ok(command) => return Some(command),
+                    Err(message) => self.print(&message),
+                }
+            },
+            Event::Typed(c) if self.open && *c != '`' && !c.is_control() => {
+                self.input.push(*c);
+            },
+            _ => {},
+        }
+        None
+    }
+
+    pub fn update(&mut self, glyphs: &GlyphSet) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        self.label.visible = self.open;
+        let mut text = self.history.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(PROMPT);
+        text.push_str(&self.input);
+        // Markup typed at the prompt is shown as typed
+        self.label.set_text(&text.replace('[', "[[").replace('{', "{{"));
+        self.label.update(glyphs);
+    }
+
+    pub fn draw(&self, window: &mut Window) {
+        if !self.open {
+            return;
+        }
+        let height = (HISTORY + 1) as f32 * TEXT_SIZE + 20.0;
+        window.draw_ex(
+            &Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: self.width, y: height }),
+            Col(BG_COLOR),
+            Transform::IDENTITY,
+            HUD_Z + 1.0
+        );
+        self.label.draw(window, HUD_Z + 2.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_lines_list_the_commands() {
+        assert!(parse("").err().unwrap().starts_with("commands:"));
+        assert!(parse("   ").is_err());
+        assert_eq!(parse("jump").err(), Some("unknown command jump".to_owned()));
+    }
+
+    #[test]
+    fn spawn_defaults_to_one() {
+        match parse("spawn coin") {
+            Ok(Command::Spawn(glyph, 1)) => assert_eq!(glyph, "coin"),
+            _ => panic!("expected one coin"),
+        }
+        match parse("spawn coin 5") {
+            Ok(Command::Spawn(_, 5)) => {},
+            _ => panic!("expected five coins"),
+        }
+        assert_eq!(parse("spawn").err(), Some("spawn: missing argument".to_owned()));
+        assert!(parse("spawn coin -1").is_err());
+    }
+
+    #[test]
+    fn timescale_must_be_finite_and_positive() {
+        match parse("timescale 0.5") {
+            Ok(Command::TimeScale(t)) => assert_eq!(t, 0.5),
+            _ => panic!("expected a timescale"),
+        }
+        for bad in ["nan", "inf", "-inf", "-1", "0", "fast"].iter() {
+            assert!(parse(&format!("timescale {}", bad)).is_err(), "{}", bad);
+        }
+        assert!(parse("timescale").is_err());
+    }
+
+    #[test]
+    fn seed_takes_a_whole_number() {
+        match parse("seed 42") {
+            Ok(Command::Seed(42)) => {},
+            _ => panic!("expected seed 42"),
+        }
+        assert!(parse("seed 4.2").is_err());
+        assert!(parse("seed -1").is_err());
+    }
+
+    #[test]
+    fn toggles_take_no_arguments() {
+        assert!(match parse("god") { Ok(Command::God) => true, _ => false });
+        assert!(match parse("shields") { Ok(Command::Shields) => true, _ => false });
+    }
+
+    #[test]
+    fn tween_takes_a_property_frames_and_ease() {
+        match parse("tween position 10,20 30 backout") {
+            Ok(Command::Tween(Property::Position(v), frames, Ease::BackOut)) => {
+                assert_eq!(v, Vector{ x: 10.0, y: 20.0 });
+                assert_eq!(frames, 30.0);
+            },
+            _ => panic!("expected a position tween"),
+        }
+        match parse("tween tint #ff0000") {
+            Ok(Command::Tween(Property::Tint(color), frames, Ease::Linear)) => {
+                assert_eq!(color, Color::RED);
+                assert_eq!(frames, TWEEN_FRAMES);
+            },
+            _ => panic!("expected a tint tween"),
+        }
+        assert!(parse("tween wobble 1").is_err());
+        assert!(parse("tween rotation nan").is_err());
+        assert!(parse("tween z 1 0").is_err());
+        assert!(parse("tween z 1 10 wobble").is_err());
+    }
+}
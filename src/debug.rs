@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use quicksilver::{
+    geom::{Line, Rectangle, Transform, Vector},
+    graphics::{Background::Col, Color, Mesh, ShapeRenderer},
+    lifecycle::Window,
+    lyon::{
+        math::Point,
+        tessellation::{StrokeOptions, StrokeTessellator},
+    },
+};
+
+use crate::glyph::{ColorMode, Glyph, GlyphId, GlyphSet};
+use crate::hud::HUD_Z;
+use crate::path_convert::{convert_segments, Segment};
+use crate::profile::{self, Profiler};
+use crate::world::World;
+
+const BOUNDS_COLOR: Color = Color{ r: 0.2, g: 1.0, b: 0.4, a: 0.8 };
+const VELOCITY_COLOR: Color = Color{ r: 1.0, g: 0.8, b: 0.2, a: 0.8 };
+const PIVOT_COLOR: Color = Color{ r: 1.0, g: 0.3, b: 0.9, a: 0.8 };
+// A ring rather than a dot, so what's under the pivot still shows
+const PIVOT_RADIUS: f32 = 4.0;
+const PIVOT_WIDTH: f32 = 1.5;
+// Of the outlines drawn around each sprite's paths, before scaling
+const OUTLINE_WIDTH: f32 = 1.5;
+const OUTLINE_TOLERANCE: f32 = 0.5;
+// Frames of movement the velocity lines are drawn long
+const VELOCITY_SCALE: f32 = 10.0;
+// Room for the stats and a few dozen profiler scopes, so the text isn't
+// reallocated as timings grow a digit
+const TEXT_CAPACITY: usize = 1024;
+// Below the overlay text
+const GRAPH_POS: Vector = Vector{ x: 10.0, y: 260.0 };
+
+// Counts shown by the overlay, filled in by the game every frame
+#[derive(Default)]
+pub struct Stats {
+    pub fps: f64,
+    pub average_fps: f64,
+    pub entities: usize,
+    pub triangles: usize,
+    pub triangles_max: usize,
+    pub vertices: usize,
+    pub seed: u64,
+    // Heap allocations in the last frame, with the `count-allocations` feature
+    pub allocations: Option<usize>,
+}
+
+// Two half circles around the origin
+fn pivot() -> Glyph {
+    let half = |x: f32| Segment::ArcTo{
+        radii: Vector{ x: PIVOT_RADIUS, y: PIVOT_RADIUS },
+        x_rotation: 0.0,
+        large_arc: false,
+        sweep: true,
+        to: Point::new(x, 0.0),
+    };
+    let segments = vec![
+        Segment::MoveTo(Point::new(PIVOT_RADIUS, 0.0)),
+        half(-PIVOT_RADIUS),
+        half(PIVOT_RADIUS),
+        Segment::Close,
+    ];
+    let mut mesh = Mesh::new();
+    // Just a circle, nothing for lyon to fail on
+    let _ = StrokeTessellator::new().tessellate_path(
+        convert_segments(segments, None),
+        &StrokeOptions::tolerance(OUTLINE_TOLERANCE).with_line_width(PIVOT_WIDTH),
+        &mut ShapeRenderer::new(&mut mesh, Color::WHITE));
+    Glyph::new("pivot".to_owned(), mesh)
+}
+
+pub struct Overlay {
+    pub visible: bool,
+    // Draw every sprite's bounds and velocity
+    pub shapes: bool,
+    pub stats: Stats,
+    // Both rebuilt in place, so showing the overlay doesn't allocate
+    text: String,
+    graph: Glyph,
+    // Built the first time they are shown with `shapes` on
+    pivot: Option<Glyph>,
+    outlines: HashMap<GlyphId, Glyph>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay{
+            visible: true,
+            shapes: false,
+            stats: Stats::default(),
+            text: String::with_capacity(TEXT_CAPACITY),
+            graph: Glyph::from_mesh(
+                "profile graph".to_owned(),
+                Mesh::new(),
+                Rectangle::new(Vector{ x: 0.0, y: 0.0 }, profile::GRAPH_SIZE)
+            ),
+            pivot: None,
+            outlines: HashMap::new(),
+        }
+    }
+
+    // The stats and the profiler's summary
+    pub fn update_text(&mut self, profiler: &Profiler) -> &str {
+        self.text.clear();
+        // Writing to a String can't fail
+        let _ = write!(
+            self.text,
+            "fps: {:.0} / {:.0}\nentities: {}\ntri: {} / {}\nvert: {}\nseed: {}\n",
+            self.stats.fps,
+            self.stats.average_fps,
+            self.stats.entities,
+            self.stats.triangles,
+            self.stats.triangles_max,
+            self.stats.vertices,
+            self.stats.seed
+        );
+        if let Some(count) = self.stats.allocations {
+            let _ = write!(self.text, "alloc: {}\n", count);
+        }
+        profiler.write_summary(&mut self.text);
+        &self.text
+    }
+
+    pub fn update_graph(&mut self, profiler: &Profiler) {
+        profiler.graph(&mut self.graph.lods[0].mesh);
+    }
+
+    pub fn draw_graph(&self, window: &mut Window) {
+        if self.visible {
+            window.draw_ex(&self.graph, Col(Color::WHITE), Transform::translate(GRAPH_POS), HUD_Z);
+        }
+    }
+
+    // After the glyphs change
+    pub fn clear_outlines(&mut self) {
+        self.outlines.clear();
+    }
+
+    pub fn draw_shapes(&mut self, window: &mut Window, world: &World, glyphs: &GlyphSet) {
+        if !self.shapes {
+            return;
+        }
+        let pivot = self.pivot.get_or_insert_with(pivot);
+        for (entity, sprite) in world.sprites.iter().filter(|(_, s)| s.visible) {
+            let placed = match world.transforms.get(entity) {
+                Some(placed) => placed,
+                None => continue,
+            };
+            let pos = placed.pos;
+            let glyph = glyphs.get_id(sprite.glyph);
+            let outline = self.outlines.entry(sprite.glyph)
+                .or_insert_with(|| glyph.outline(OUTLINE_WIDTH, OUTLINE_TOLERANCE));
+            window.draw_ex(&outline.painted(ColorMode::Tint).transformed(placed.transform), Col(BOUNDS_COLOR), Transform::IDENTITY, HUD_Z);
+            let corners = placed.corners(glyph);
+            for i in 0..4 {
+                window.draw_ex(&Line::new(corners[i], corners[(i + 1) % 4]), Col(BOUNDS_COLOR), Transform::IDENTITY, HUD_Z);
+            }
+            window.draw_ex(&pivot.painted(ColorMode::Tint), Col(PIVOT_COLOR), Transform::translate(pos), HUD_Z);
+            if let Some(velocity) = world.velocities.get(entity) {
+                window.draw_ex(
+                    &Line::new(pos, pos + *velocity * VELOCITY_SCALE).with_thickness(2.0),
+                    Col(VELOCITY_COLOR),
+                    Transform::IDENTITY,
+                    HUD_Z
+                );
+            }
+        }
+    }
+}
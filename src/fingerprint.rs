@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, HashMap};
+
+use quicksilver::graphics::Mesh;
+
+use crate::glyph::{Glyph, GlyphSet};
+use crate::glyph_path::points_bounds;
+
+// Checked in next to the sheet, one line per glyph. The test at the
+// bottom compares the sheet against them.
+pub const EXPECTATIONS_PATH: &str = "assets/glyphs.fingerprints";
+// Vertex colours are counted by their top bits, so tiny gradient
+// differences don't show
+const COLOR_LEVELS: f32 = 15.0;
+// Bounds are compared to this fraction of a unit
+const BOUNDS_PRECISION: f32 = 10.0;
+
+fn round(value: f32) -> f32 {
+    (value * BOUNDS_PRECISION).round() / BOUNDS_PRECISION
+}
+
+// Vertex colours by count, as `rgba` in hex digits
+fn histogram(mesh: &Mesh) -> BTreeMap<String, usize> {
+    let level = |c: f32| (c.max(0.0).min(1.0) * COLOR_LEVELS).round() as u8;
+    let mut histogram = BTreeMap::new();
+    for v in mesh.vertices.iter() {
+        let key = format!("{:x}{:x}{:x}{:x}", level(v.col.r), level(v.col.g), level(v.col.b), level(v.col.a));
+        *histogram.entry(key).or_insert(0) += 1;
+    }
+    histogram
+}
+
+// What a glyph's finest mesh looks like, as one line of text
+pub fn fingerprint(name: &str, glyph: &Glyph) -> String {
+    let mesh = &glyph.lods[0].mesh;
+    let bounds = match points_bounds(mesh.vertices.iter().map(|v| &v.pos)) {
+        Some(b) => format!("{},{},{},{}", round(b.pos.x), round(b.pos.y), round(b.size.x), round(b.size.y)),
+        None => "none".to_owned(),
+    };
+    let colors: Vec<String> = histogram(mesh).iter()
+        .map(|(color, count)| format!("{}:{}", color, count))
+        .collect();
+    format!(
+        "{} vertices={} triangles={} bounds={} colors={}",
+        name, mesh.vertices.len(), mesh.triangles.len(), bounds, colors.join(",")
+    )
+}
+
+// Every glyph loaded from the sheet, sorted by name
+pub fn fingerprints(glyphs: &GlyphSet) -> Vec<(String, String)> {
+    let mut fingerprints: Vec<(String, String)> = glyphs.iter()
+        .filter(|(_, glyph)| !glyph.paths.is_empty())
+        .map(|(name, glyph)| (name.to_owned(), fingerprint(name, glyph)))
+        .collect();
+    fingerprints.sort();
+    fingerprints
+}
+
+pub fn write(glyphs: &GlyphSet) -> String {
+    let mut out = String::new();
+    for (_, line) in fingerprints(glyphs) {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+// What differs from the expectations, empty when nothing does
+pub fn check(glyphs: &GlyphSet, expected: &str) -> Vec<String> {
+    let expected: HashMap<&str, &str> = expected.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| line.split_whitespace().next().map(|name| (name, line)))
+        .collect();
+    let actual = fingerprints(glyphs);
+    let mut failures = Vec::new();
+    for (name, line) in actual.iter() {
+        match expected.get(name.as_str()) {
+            Some(expected) if *expected == line => (),
+            Some(expected) => failures.push(format!("{} changed\n  expected {}\n  got      {}", name, expected, line)),
+            None => failures.push(format!("{} is new\n  got      {}", name, line)),
+        }
+        // Whatever the expectations say, a glyph from the sheet should draw
+        if glyphs.get(name).lods[0].mesh.triangles.is_empty() {
+            failures.push(format!("{} has no triangles", name));
+        }
+    }
+    let mut gone: Vec<&&str> = expected.keys()
+        .filter(|name| !actual.iter().any(|(actual, _)| actual == *name))
+        .collect();
+    gone.sort();
+    for name in gone {
+        failures.push(format!("{} is gone, it would be drawn as `missing`", name));
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_glyphs;
+
+    // Run with UPDATE_GLYPHS=1 to write the fingerprints instead, after
+    // changing the art on purpose
+    #[test]
+    fn glyphs_match_their_fingerprints() {
+        let glyphs = load_glyphs(include_bytes!("../assets/glyphs.svg")).unwrap();
+        if std::env::var("UPDATE_GLYPHS").map(|update| update == "1").unwrap_or(false) {
+            std::fs::write(EXPECTATIONS_PATH, write(&glyphs)).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(EXPECTATIONS_PATH)
+            .unwrap_or_else(|err| panic!("could not read {}, run with UPDATE_GLYPHS=1 to create it: {}", EXPECTATIONS_PATH, err));
+        let failures = check(&glyphs, &expected);
+        assert!(failures.is_empty(), "{}\n{} differences, run with UPDATE_GLYPHS=1 if the art changed on purpose",
+            failures.join("\n"), failures.len());
+    }
+}
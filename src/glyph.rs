@@ -1,184 +1,751 @@
 extern crate quicksilver;
 extern crate usvg;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use quicksilver::{
     Result,
-    geom::{Scalar, Transform, Vector},
-    graphics::{Background, Color, Drawable, GpuTriangle, Mesh, ShapeRenderer, Vertex},
+    geom::{Rectangle, Scalar, Shape, Transform, Vector},
+    graphics::{Background, Color, Drawable, GpuTriangle, Mesh, Vertex},
     lyon::{
-        tessellation::{FillOptions, FillTessellator},
+        path::PathEvent,
+        tessellation::{FillTessellator, StrokeTessellator},
     },
 };
-use crate::path_convert::convert_path;
+use crate::affine::Affine;
+use crate::anim::{self, Clip, ClipId, Frame};
+use crate::charmap::{self, CharMap, Fallback};
+use crate::clip::convex;
+use crate::glyph_path::{append_mesh, GlyphPath};
+use crate::path_convert::convert_path_with;
+use crate::svg_meta::{self, Attributes};
+use crate::text::TextLayout;
 
 pub const MISSING: &str = "missing";
 pub const COIN: &str = "coin";
 pub const HERO: &str = "hero";
 pub const ID_PREFIX: &str = "glyph_";
+// `glyph_<name>@<n>` groups are frame `n` of the clip `name`
+pub const FRAME_SEPARATOR: char = '@';
+// Marks the rect giving a glyph's bounds, also matched by an id of
+// `glyph_<name>_bounds` or an inkscape:label of `bounds`
+pub const BOUNDS_MARKER: &str = "bounds";
+// Marks a shape whose centre is the glyph's origin (or pivot)
+pub const ORIGIN_MARKER: &str = "origin";
 
 
+fn mesh_bounds(mesh: &Mesh) -> Rectangle {
+    if mesh.vertices.is_empty() {
+        return Rectangle{ pos: Vector{ x: 0.0, y: 0.0 }, size: Vector{ x: 0.0, y: 0.0 } };
+    }
+    let mut min = mesh.vertices[0].pos;
+    let mut max = min;
+    for v in mesh.vertices.iter() {
+        min = Vector{ x: min.x.min(v.pos.x), y: min.y.min(v.pos.y) };
+        max = Vector{ x: max.x.max(v.pos.x), y: max.y.max(v.pos.y) };
+    }
+    Rectangle{ pos: min, size: max - min }
+}
+
+
+// Tolerances, in glyph units, of the meshes generated for every glyph
+pub const DEFAULT_TOLERANCES: [f32; 3] = [0.1, 0.5, 2.0];
+// Largest tessellation error, in screen units, allowed when picking a LOD
+pub const MAX_SCREEN_ERROR: f32 = 0.1;
+
+pub struct Lod {
+    pub tolerance: f32,
+    pub mesh: Mesh,
+}
+
+impl Clone for Lod {
+    fn clone(&self) -> Lod {
+        let mut mesh = Mesh::new();
+        append_mesh(&self.mesh, &mut mesh);
+        Lod{ tolerance: self.tolerance, mesh: mesh }
+    }
+}
+
+// Where a glyph sits relative to the coordinates it's drawn at
+pub struct Metrics {
+    pub bounds: Rectangle,
+    pub origin: Vector,
+    pub advance: f32,
+}
+
+impl Default for Metrics {
+    // The layout every glyph on the original sheet was drawn with
+    fn default() -> Metrics {
+        Metrics{
+            bounds: Rectangle{ pos: Vector{ x: 0.0, y: 0.0 }, size: Vector{ x: 100.0, y: 100.0 } },
+            origin: Vector{ x: 50.0, y: 50.0 },
+            advance: 80.0,
+        }
+    }
+}
+
 pub struct Glyph {
     pub name: String,
-    pub mesh: Mesh,
+    // Ordered from the finest to the coarsest tolerance
+    pub lods: Vec<Lod>,
+    // Relative to the origin, which is where the glyph is drawn at
+    pub bounds: Rectangle,
+    pub advance: f32,
+    // Adjustment to `advance` when followed by the named glyph
+    pub kerning: HashMap<String, f32>,
+    // `data-*` attributes of the glyph's group (without the prefix) and
+    // its inkscape:label as `label`
+    pub attributes: Attributes,
+    // What the meshes were tessellated from, relative to the origin.
+    // Empty for glyphs not loaded from the SVG.
+    pub paths: Vec<GlyphPath>,
+}
+
+// Parses `data-kerning` lists of `<next glyph>:<adjustment>` pairs,
+// e.g. "V:-10 T:-8"
+fn parse_kerning(value: &str) -> HashMap<String, f32> {
+    value.split_whitespace()
+        .filter_map(|pair| {
+            let split = pair.rfind(':')?;
+            let amount = pair[split + 1..].parse().ok()?;
+            Some((pair[..split].to_owned(), amount))
+        })
+        .collect()
 }
 
 impl Glyph {
     pub fn new(name: String, mesh: Mesh) -> Glyph {
-        let mut centered = Mesh::new();
-        centered.vertices.extend(mesh.vertices.iter()
-            .map(|v| Vertex{pos: Vector{ x: v.pos.x - 50.0, y: v.pos.y - 50.0 }, col: v.col, tex_pos: v.tex_pos}));
-        centered.triangles.extend(mesh.triangles.iter()
-            .map(|t| GpuTriangle{
-                z:t.z,
-                indices:t.indices,
-                image: t.image.clone()}));
-        Glyph{name: name, mesh: centered}
+        Glyph::with_lods(name, vec![Lod{ tolerance: 0.0, mesh: mesh }], &Metrics::default(), HashMap::new())
     }
 
-    pub fn from_text(text: String, size: f32, background: Background, glyphs: &GlyphSet) -> Glyph {
+    pub fn with_lods(name: String, lods: Vec<Lod>, metrics: &Metrics, attributes: Attributes) -> Glyph {
+        let origin = metrics.origin;
+        let lods = lods.into_iter().map(|lod| {
+            let mut centered = Mesh::new();
+            centered.vertices.extend(lod.mesh.vertices.iter()
+                .map(|v| Vertex{pos: v.pos - origin, col: v.col, tex_pos: v.tex_pos}));
+            centered.triangles.extend(lod.mesh.triangles.iter()
+                .map(|t| GpuTriangle{
+                    z:t.z,
+                    indices:t.indices,
+                    image: t.image.clone()}));
+            Lod{ tolerance: lod.tolerance, mesh: centered }
+        }).collect();
+        Glyph{
+            name: name,
+            lods: lods,
+            bounds: Rectangle{ pos: metrics.bounds.pos - origin, size: metrics.bounds.size },
+            advance: metrics.advance,
+            kerning: attributes.get("kerning").map(|k| parse_kerning(k)).unwrap_or_default(),
+            attributes: attributes,
+            paths: Vec::new(),
+        }
+    }
+
+    // Picks the coarsest mesh whose error stays under MAX_SCREEN_ERROR
+    // once drawn at `scale`
+    pub fn lod(&self, scale: f32) -> &Mesh {
+        let mut mesh = &self.lods[0].mesh;
+        for lod in self.lods.iter().skip(1) {
+            if lod.tolerance * scale > MAX_SCREEN_ERROR {
+                break;
+            }
+            mesh = &lod.mesh;
+        }
+        mesh
+    }
+
+    // A glyph drawn exactly as `mesh`, without recentring it
+    pub fn from_mesh(name: String, mesh: Mesh, bounds: Rectangle) -> Glyph {
+        Glyph{
+            name: name,
+            lods: vec![Lod{ tolerance: 0.0, mesh: mesh }],
+            bounds: bounds,
+            advance: bounds.pos.x + bounds.size.x,
+            kerning: HashMap::new(),
+            attributes: HashMap::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    // All the paths as one mesh
+    pub fn tessellate(&self, tolerance: f32, fill: &mut FillTessellator, stroke: &mut StrokeTessellator) -> Mesh {
         let mut mesh = Mesh::new();
-        let mut x = 0.0;
-        let mut y = 0.0;
-        let scale = size/100.0;
-        let width = 80.0*scale;
-        let height = 100.0*scale;
-        for c in text.chars() {
-            let name = match c {
-                ' '  => "space".to_string(),
-                '.'  => "period".to_string(),
-                '!'  => "exclamation_point".to_string(),
-                '/'  => "slash".to_string(),
-                '\\' => "backslash".to_string(),
-                '|'  => "pipe".to_string(),
-                '['  => "left_bracket".to_string(),
-                ']'  => "right_bracket".to_string(),
-                '{'  => "left_curly_brace".to_string(),
-                '}'  => "right_curly_brace".to_string(),
-                ','  => "comma".to_string(),
-                ';'  => "semicolon".to_string(),
-                ':'  => "colon".to_string(),
-                '\'' => "single_quote".to_string(),
-                '"'  => "double_quote".to_string(),
-                '&'  => "ampersand".to_string(),
-                '\n' => {
-                            x = 0.0;
-                            y += height;
-                            continue;
-                        },
-                _    => c.to_string()
-            };
-            let transform = 
-                Transform::translate(Vector{x:x, y:y}) *
-                Transform::scale(Vector{x:scale, y:scale});
-            let glyph = glyphs.get(&name);
-            glyph.draw(&mut mesh, background, transform, 0.0);
-            x += width;
+        for path in self.paths.iter() {
+            path.tessellate(tolerance, fill, stroke, &mut mesh);
+        }
+        mesh
+    }
+
+    // Rebuilds the meshes from the paths, if there are any
+    pub fn retessellate(&mut self, tolerances: &[f32], fill: &mut FillTessellator, stroke: &mut StrokeTessellator) {
+        if self.paths.is_empty() {
+            return;
         }
+        self.lods = tolerances.iter()
+            .map(|tolerance| Lod{ tolerance: *tolerance, mesh: self.tessellate(*tolerance, fill, stroke) })
+            .collect();
+    }
 
-        Glyph{name: text, mesh: mesh}
+    // The outline of every path, `width` wide and white
+    pub fn outline(&self, width: f32, tolerance: f32) -> Glyph {
+        let mut mesh = Mesh::new();
+        let mut tessellator = StrokeTessellator::new();
+        for path in self.paths.iter() {
+            path.stroke_into(width, tolerance, &mut tessellator, &mut mesh);
+        }
+        Glyph::from_mesh(format!("{} outline", self.name), mesh, self.bounds)
+    }
+
+    pub fn from_text(text: String, size: f32, background: Background, glyphs: &GlyphSet) -> Glyph {
+        TextLayout::new(size).build(&text, background, glyphs)
     }
 
 }
 
 
 
-impl Drawable for Glyph {
+// How the Background passed to `draw` combines with a glyph's own colours
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+    // Multiplies them, so white leaves the glyph as it is in the SVG
+    Tint,
+    // Replaces them, keeping only their alpha
+    Replace,
+}
 
-    fn draw<'a>(&self, dest: &mut Mesh, _bg: Background<'a>, transform: Transform, z: impl Scalar){
+impl ColorMode {
+    fn apply(self, color: Color, background: Color) -> Color {
+        match self {
+            ColorMode::Tint => tint(color, background),
+            ColorMode::Replace => Color{ a: color.a * background.a, ..background },
+        }
+    }
+}
+
+// A glyph drawn with a ColorMode other than the default tint, or with
+// an Affine transform quicksilver's Transform can't express (skews)
+pub struct Painted<'g> {
+    glyph: &'g Glyph,
+    mode: ColorMode,
+    // Applied before the transform passed to `draw`
+    transform: Affine,
+}
+
+impl<'g> Painted<'g> {
+    pub fn transformed(self, transform: Affine) -> Painted<'g> {
+        Painted{ transform: transform, ..self }
+    }
+}
+
+impl Glyph {
+    pub fn painted(&self, mode: ColorMode) -> Painted<'_> {
+        Painted{ glyph: self, mode: mode, transform: Affine::IDENTITY }
+    }
+
+    fn draw_with<'a>(&self, dest: &mut Mesh, bg: Background<'a>, transform: Affine, z: f32, mode: ColorMode) {
+        let mesh = self.lod(transform.max_scale());
+        // Images are stretched over the glyph's bounds
+        let projection = bg.image().map(|img| img.projection(self.bounds));
+        let color = |col: Color| match bg {
+            Background::Col(c) | Background::Blended(_, c) => mode.apply(col, c),
+            Background::Img(_) => mode.apply(col, Color::WHITE),
+        };
         let offset = dest.vertices.len() as u32;
-        dest.vertices.extend(self.mesh.vertices.iter()
-            .map(|v| Vertex{pos: transform * v.pos, col: v.col, tex_pos: v.tex_pos}));
-        dest.triangles.extend(self.mesh.triangles.iter()
+        dest.vertices.extend(mesh.vertices.iter()
+            .map(|v| Vertex{
+                pos: transform * v.pos,
+                col: color(v.col),
+                tex_pos: projection.map(|p| p * v.pos).or(v.tex_pos),
+            }));
+        dest.triangles.extend(mesh.triangles.iter()
             .map(|t| GpuTriangle{
-                z:t.z + z.float(),
+                z:t.z + z,
                 indices:[t.indices[0]+offset, t.indices[1]+offset, t.indices[2]+offset],
-                image: t.image.clone()}));
+                image: bg.image().cloned().or_else(|| t.image.clone())}));
+    }
+}
+
+impl Drawable for Glyph {
+
+    fn draw<'a>(&self, dest: &mut Mesh, bg: Background<'a>, transform: Transform, z: impl Scalar){
+        self.draw_with(dest, bg, Affine::from_transform(transform), z.float(), ColorMode::Tint);
+    }
+}
+
+impl<'g> Drawable for Painted<'g> {
+
+    fn draw<'a>(&self, dest: &mut Mesh, bg: Background<'a>, transform: Transform, z: impl Scalar){
+        self.glyph.draw_with(dest, bg, Affine::from_transform(transform) * self.transform, z.float(), self.mode);
     }
 }
 
 impl Clone for Glyph {
     fn clone(&self) -> Glyph {
-        let mut mesh = Mesh::new();
-        mesh.vertices.extend(self.mesh.vertices.iter()
-            .map(|v| Vertex{pos: v.pos, col: v.col, tex_pos: v.tex_pos}));
-        mesh.triangles.extend(self.mesh.triangles.iter()
-            .map(|t| GpuTriangle{
-                z:t.z,
-                indices:t.indices,
-                image: t.image.clone()}));
-        Glyph{ name: self.name.clone(), mesh: mesh }
+        Glyph{
+            name: self.name.clone(),
+            lods: self.lods.clone(),
+            bounds: self.bounds,
+            advance: self.advance,
+            kerning: self.kerning.clone(),
+            attributes: self.attributes.clone(),
+            paths: self.paths.clone(),
+        }
     }
 }
 
+// A handle to a glyph in a GlyphSet, cheaper to keep and look up than its
+// name. Replacing a glyph keeps its id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GlyphId(usize);
+
+// Always the first glyph in a set
+pub const MISSING_ID: GlyphId = GlyphId(0);
+
 pub struct GlyphSet {
-    glyphs: HashMap<String, Glyph>,
+    glyphs: Vec<Glyph>,
+    ids: HashMap<String, GlyphId>,
+    clips: Vec<Clip>,
+    clip_ids: HashMap<String, ClipId>,
+    // Frame event names, kept for the whole game so they can be sent as
+    // `&'static str` like the tweens' events
+    events: HashSet<&'static str>,
+    tolerances: Vec<f32>,
+    chars: CharMap,
+}
+
+
+// Multiplies `color` by `tint`, channel by channel
+pub fn tint(color: Color, tint: Color) -> Color {
+    Color{
+        r: color.r * tint.r,
+        g: color.g * tint.g,
+        b: color.b * tint.b,
+        a: color.a * tint.a,
+    }
+}
+
+
+// Accumulated state of the groups above a node, relative to its glyph
+struct Context {
+    transform: Affine,
+    opacity: f32,
+    clips: Vec<Vec<Vector>>,
+}
+
+// Polygon and alpha of a clip path or mask. Only clips made of a single
+// convex, straight edged path in user space units are supported (which
+// covers the rectangles Inkscape produces), masks are approximated as a
+// clip by their shape scaled by the luminance of its fill.
+fn clip_shape(rtree: &usvg::Tree, id: &str) -> Option<(Vec<Vector>, f32)> {
+    let node = rtree.defs_by_id(id)?;
+    let transform = match *node.borrow() {
+        usvg::NodeKind::ClipPath(ref c) if c.units == usvg::Units::UserSpaceOnUse =>
+            Affine::from_usvg(&c.transform),
+        usvg::NodeKind::Mask(ref m) if m.content_units == usvg::Units::UserSpaceOnUse =>
+            Affine::IDENTITY,
+        _ => return None,
+    };
+    let is_mask = if let usvg::NodeKind::Mask(_) = *node.borrow() { true } else { false };
+
+    let mut children = node.children();
+    let child = children.next()?;
+    if children.next().is_some() {
+        return None;
+    }
+    let shape = match *child.borrow() {
+        usvg::NodeKind::Path(ref p) => {
+            let transform = transform * Affine::from_usvg(&p.transform);
+            let mut points = Vec::new();
+            for event in convert_path_with(p, Some(transform)) {
+                match event {
+                    PathEvent::MoveTo(to) if points.is_empty() =>
+                        points.push(Vector{ x: to.x, y: to.y }),
+                    PathEvent::Line(segment) =>
+                        points.push(Vector{ x: segment.to.x, y: segment.to.y }),
+                    PathEvent::Close(_) => {},
+                    _ => return None,
+                }
+            }
+            if points.len() > 1 && points[0] == points[points.len() - 1] {
+                points.pop();
+            }
+            let alpha = match p.fill {
+                Some(ref fill) if is_mask => match fill.paint {
+                    usvg::Paint::Color(col) => fill.opacity.value() as f32 *
+                        (0.2125 * col.red as f32 + 0.7154 * col.green as f32 + 0.0721 * col.blue as f32) / 255.0,
+                    _ => fill.opacity.value() as f32,
+                },
+                _ => 1.0,
+            };
+            (convex(points)?, alpha)
+        },
+        _ => return None,
+    };
+    Some(shape)
+}
+
+impl Context {
+    fn new() -> Context {
+        Context{ transform: Affine::IDENTITY, opacity: 1.0, clips: Vec::new() }
+    }
+
+    // `transform` is passed separately so a glyph's root group can ignore
+    // the translation that positions it on the sheet
+    fn enter(&self, rtree: &usvg::Tree, g: &usvg::Group, transform: Affine) -> Context {
+        let transform = self.transform * transform;
+        let mut opacity = self.opacity * g.opacity.map(|o| o.value()).unwrap_or(1.0) as f32;
+        let mut clips = self.clips.clone();
+        for id in g.clip_path.iter().chain(g.mask.iter()) {
+            if let Some((shape, alpha)) = clip_shape(rtree, id) {
+                // Clip coordinates are in the group's user space
+                clips.push(shape.into_iter().map(|p| transform * p).collect());
+                opacity *= alpha;
+            }
+        }
+        Context{ transform: transform, opacity: opacity, clips: clips }
+    }
+
 }
 
+// Bounds and origin shapes found in a glyph's group, in glyph space
+struct Markers {
+    bounds: Option<Rectangle>,
+    origin: Option<Vector>,
+    ids: Vec<String>,
+}
+
+fn marker_kind(group_id: &str, id: &str, attributes: &Attributes) -> Option<&'static str> {
+    for kind in [BOUNDS_MARKER, ORIGIN_MARKER].iter() {
+        if id == format!("{}_{}", group_id, kind)
+            || svg_meta::has_class(attributes, &format!("glyph-{}", kind))
+            || attributes.get("inkscape:label").map(|l| l == kind).unwrap_or(false) {
+            return Some(kind);
+        }
+    }
+    None
+}
+
+// Bounds of a path's points and control points
+fn path_bounds(p: &usvg::Path, transform: Affine) -> Option<Rectangle> {
+    let mut points = Vec::new();
+    for event in convert_path_with(p, Some(transform)) {
+        match event {
+            PathEvent::MoveTo(to) => points.push(to),
+            PathEvent::Line(s) => points.push(s.to),
+            PathEvent::Quadratic(s) => points.extend_from_slice(&[s.ctrl, s.to]),
+            PathEvent::Cubic(s) => points.extend_from_slice(&[s.ctrl1, s.ctrl2, s.to]),
+            PathEvent::Close(_) => {},
+        }
+    }
+    let mut points = points.into_iter().map(|p| Vector{ x: p.x, y: p.y });
+    let first = points.next()?;
+    let (min, max) = points.fold((first, first), |(min, max), p| (
+        Vector{ x: min.x.min(p.x), y: min.y.min(p.y) },
+        Vector{ x: max.x.max(p.x), y: max.y.max(p.y) },
+    ));
+    Some(Rectangle{ pos: min, size: max - min })
+}
+
+fn collect_paths(rtree: &usvg::Tree, node: &usvg::Node, context: &Context, markers: &Markers, paths: &mut Vec<GlyphPath>) {
+    for child in node.children() {
+        match *child.borrow() {
+            usvg::NodeKind::Group(ref g) => {
+                let context = context.enter(rtree, g, Affine::from_usvg(&g.transform));
+                collect_paths(rtree, &child, &context, markers, paths);
+            },
+            usvg::NodeKind::Path(ref p) => {
+                if markers.ids.contains(&p.id) {
+                    continue;
+                }
+                let transform = context.transform * Affine::from_usvg(&p.transform);
+                paths.push(GlyphPath::from_usvg(rtree, p, transform, context.opacity, context.clips.clone()));
+            },
+            _ => {},
+        }
+    }
+}
+
+fn find_markers(node: &usvg::Node, transform: Affine, meta: &HashMap<String, Attributes>, group_id: &str, markers: &mut Markers) {
+    for child in node.children() {
+        match *child.borrow() {
+            usvg::NodeKind::Group(ref g) =>
+                find_markers(&child, transform * Affine::from_usvg(&g.transform), meta, group_id, markers),
+            usvg::NodeKind::Path(ref p) => {
+                let kind = match meta.get(&p.id) {
+                    Some(attributes) => marker_kind(group_id, &p.id, attributes),
+                    None => None,
+                };
+                let bounds = path_bounds(p, transform * Affine::from_usvg(&p.transform));
+                match (kind, bounds) {
+                    (Some(BOUNDS_MARKER), Some(bounds)) => markers.bounds = Some(bounds),
+                    (Some(ORIGIN_MARKER), Some(bounds)) => markers.origin = Some(bounds.center()),
+                    _ => continue,
+                }
+                markers.ids.push(p.id.clone());
+            },
+            _ => {},
+        }
+    }
+}
+
+fn parse_point(value: &str) -> Option<Vector> {
+    let mut parts = value.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty());
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    Some(Vector{ x: x, y: y })
+}
+
+
+
 impl GlyphSet {
     pub fn new() -> Result<GlyphSet> {
-        let mut glyphs: HashMap<String, Glyph> = HashMap::new();
-        glyphs.insert(MISSING.to_owned(), Glyph::new(MISSING.to_owned(), Mesh::new()));
+        let mut ids = HashMap::new();
+        ids.insert(MISSING.to_owned(), MISSING_ID);
         Ok(GlyphSet{
-            glyphs: glyphs,
+            glyphs: vec![Glyph::new(MISSING.to_owned(), Mesh::new())],
+            ids: ids,
+            clips: Vec::new(),
+            clip_ids: HashMap::new(),
+            events: HashSet::new(),
+            tolerances: DEFAULT_TOLERANCES.to_vec(),
+            chars: CharMap::new(),
         })
     }
 
+    // The id of the named glyph, or of `missing` if there isn't one
+    pub fn id(&self, key: &str) -> GlyphId {
+        self.ids.get(key).cloned().unwrap_or(MISSING_ID)
+    }
+
     pub fn get(&self, key: &str) -> &Glyph {
-        match self.glyphs.get(key) {
+        self.get_id(self.id(key))
+    }
+
+    pub fn get_id(&self, id: GlyphId) -> &Glyph {
+        &self.glyphs[id.0]
+    }
+
+    fn lookup(&self, key: &str) -> Option<&Glyph> {
+        self.ids.get(key).map(|id| self.get_id(*id))
+    }
+
+    // `set` picks between variants of a glyph by name prefix, so with
+    // `old_` the hero is drawn as `old_hero` when there is one
+    fn find(&self, key: &str, set: Option<&str>) -> Option<&Glyph> {
+        set.and_then(|prefix| self.lookup(&format!("{}{}", prefix, key)))
+            .or_else(|| self.lookup(key))
+    }
+
+    pub fn get_in(&self, key: &str, set: Option<&str>) -> &Glyph {
+        match self.find(key, set) {
             Some(glyph) => glyph,
-            None => self.glyphs.get(MISSING).unwrap(),
+            None => self.get(MISSING),
         }
     }
 
-    pub fn insert(&mut self, key: &str, glyph: Glyph) -> Option<Glyph>{
-        self.glyphs.insert(key.to_owned(), glyph)
+    // Every glyph by name, including names that share a glyph
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Glyph)> {
+        self.ids.iter().map(move |(key, id)| (key.as_str(), self.get_id(*id)))
+    }
+
+    // Every glyph by name, laid out in rows at most `width` wide with
+    // `spacing` around them, with the top left corner of each
+    pub fn sheet(&self, width: f32, spacing: f32) -> Vec<(&str, &Glyph, Vector)> {
+        let mut entries: Vec<(&str, &Glyph)> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut sheet = Vec::with_capacity(entries.len());
+        let mut corner = Vector{ x: 0.0, y: 0.0 };
+        let mut row_height: f32 = 0.0;
+        for (name, glyph) in entries {
+            if corner.x > 0.0 && corner.x + glyph.bounds.size.x > width {
+                corner = Vector{ x: 0.0, y: corner.y + row_height + spacing };
+                row_height = 0.0;
+            }
+            sheet.push((name, glyph, corner));
+            corner.x += glyph.bounds.size.x + spacing;
+            row_height = row_height.max(glyph.bounds.size.y);
+        }
+        sheet
     }
 
-    pub fn load_from_svg_bytes(&mut self, bytes: &[u8]){
-        let mut tessellator = FillTessellator::new();
-        let default_color: Color = Color{ r: 0.6, g: 0.9, b: 0.6, a: 1.0};
+    // Replaces any glyph of the same name, keeping its id
+    pub fn insert(&mut self, key: &str, glyph: Glyph) -> GlyphId {
+        self.chars.insert_implicit(key);
+        match self.ids.get(key).cloned() {
+            Some(id) => {
+                self.glyphs[id.0] = glyph;
+                id
+            },
+            None => {
+                let id = GlyphId(self.glyphs.len());
+                self.glyphs.push(glyph);
+                self.ids.insert(key.to_owned(), id);
+                id
+            },
+        }
+    }
+
+    // The glyph drawn for `c`, going through the fallbacks for characters
+    // without one and ending with `missing`
+    pub fn get_char_in(&self, c: char, set: Option<&str>) -> &Glyph {
+        for candidate in charmap::candidates(c, &self.chars.fallbacks) {
+            if let Some(glyph) = self.chars.get(candidate).and_then(|name| self.find(name, set)) {
+                return glyph;
+            }
+        }
+        for fallback in self.chars.fallbacks.iter() {
+            if let Fallback::Glyph(ref name) = *fallback {
+                if let Some(glyph) = self.find(name, set) {
+                    return glyph;
+                }
+            }
+        }
+        self.get(MISSING)
+    }
+
+    pub fn set_fallbacks(&mut self, fallbacks: Vec<Fallback>) {
+        self.chars.fallbacks = fallbacks;
+    }
+
+    // One mesh is generated per tolerance. Glyphs already loaded from the
+    // SVG are tessellated again from their paths.
+    pub fn set_tolerances(&mut self, tolerances: &[f32]) {
+        // lyon can't tessellate to a NaN, infinite or zero tolerance
+        let mut tolerances: Vec<f32> = tolerances.iter()
+            .cloned()
+            .filter(|t| t.is_finite() && *t > 0.0)
+            .collect();
+        tolerances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        if !tolerances.is_empty() {
+            let (mut fill, mut stroke) = (FillTessellator::new(), StrokeTessellator::new());
+            for glyph in self.glyphs.iter_mut() {
+                glyph.retessellate(&tolerances, &mut fill, &mut stroke);
+            }
+            self.tolerances = tolerances;
+        }
+    }
 
+    // Loading a sheet again replaces its glyphs in place, so ids handed out
+    // for them stay valid
+    pub fn load_from_svg_bytes(&mut self, bytes: &[u8]) -> std::result::Result<(), usvg::Error> {
         let mut svg_opt = usvg::Options::default();
         svg_opt.keep_named_groups = true;
-        let rtree = usvg::Tree::from_data(bytes, &svg_opt).unwrap();
+        let rtree = usvg::Tree::from_data(bytes, &svg_opt)?;
+        let meta = svg_meta::parse(bytes);
+        let mut loaded = HashSet::new();
+        let (mut fill, mut stroke) = (FillTessellator::new(), StrokeTessellator::new());
+        // (frame number, glyph, duration in ms, event) by clip name
+        let mut frames: HashMap<String, Vec<(usize, GlyphId, f32, Option<String>)>> = HashMap::new();
         for node in rtree.root().descendants() {
             if let usvg::NodeKind::Group(ref g) = *node.borrow() {
                 let group = g.id.clone();
                 let group_len = group.chars().count();
                 if group.starts_with(ID_PREFIX) && group_len > 6{
                     let key: String = group.chars().skip(6).take(group_len-6).collect();
-                    let mut mesh = Mesh::new();
-                    let mut first = true;
-                    for group_node in node.descendants() {
-                        if let usvg::NodeKind::Path(ref p) = *group_node.borrow() {
-                            if first {
-                                // Skip the first element of the group (bounding rect)
-                                first = false;
-                                continue;
+                    let group_meta = meta.get(&group).cloned().unwrap_or_default();
+
+                    let mut markers = Markers{ bounds: None, origin: None, ids: Vec::new() };
+                    find_markers(&node, Affine::IDENTITY, &meta, &group, &mut markers);
+
+                    let context = Context::new().enter(&rtree, g, Affine::IDENTITY);
+                    let mut paths = Vec::new();
+                    collect_paths(&rtree, &node, &context, &markers, &mut paths);
+                    let meshes: Vec<Mesh> = self.tolerances.iter()
+                        .map(|tolerance| {
+                            let mut mesh = Mesh::new();
+                            for path in paths.iter() {
+                                path.tessellate(*tolerance, &mut fill, &mut stroke, &mut mesh);
                             }
-                            let color = if let Some(ref fill) = p.fill {
-                                match fill.paint {
-                                    usvg::Paint::Color(col) =>  Color::from_rgba(
-                                        col.red,
-                                        col.green,
-                                        col.blue,
-                                        fill.opacity.value() as f32
-                                    ),
-                                    _ => default_color,
-                                }
-                            } else { default_color };
-                            let mut shape_renderer = ShapeRenderer::new(&mut mesh, color);
-                            tessellator.tessellate_path(
-                                convert_path(p),
-                                &FillOptions::tolerance(0.1), &mut shape_renderer)
-                            .unwrap();
+                            mesh
+                        })
+                        .collect();
+                    //println!("Found {} {}", key, meshes[0].vertices.len());
+
+                    // Without a bounds rect, the art itself is the bounds
+                    let bounds = markers.bounds.unwrap_or_else(|| mesh_bounds(&meshes[0]));
+                    let origin = group_meta.get("data-origin").and_then(|o| parse_point(o))
+                        .or(markers.origin)
+                        .unwrap_or_else(|| bounds.center());
+                    let advance = group_meta.get("data-advance").and_then(|a| a.parse().ok())
+                        .unwrap_or(bounds.size.x * 0.8);
+                    let metrics = Metrics{ bounds: bounds, origin: origin, advance: advance };
+
+                    let mut attributes: Attributes = group_meta.iter()
+                        .filter(|(name, _)| name.starts_with("data-"))
+                        .map(|(name, value)| (name["data-".len()..].to_owned(), value.clone()))
+                        .collect();
+                    if let Some(label) = group_meta.get("inkscape:label") {
+                        attributes.insert("label".to_owned(), label.clone());
+                    }
+
+                    // Every character of `data-char` is drawn with this glyph
+                    if let Some(chars) = attributes.get("char") {
+                        for c in chars.chars() {
+                            self.chars.insert(c, &key);
                         }
                     }
-                    //println!("Found {} {}", key, mesh.vertices.len());
-                    self.glyphs.insert(key.to_owned(), Glyph::new(key, mesh));
+
+                    let lods = self.tolerances.iter().zip(meshes.into_iter())
+                        .map(|(tolerance, mesh)| Lod{ tolerance: *tolerance, mesh: mesh })
+                        .collect();
+                    let duration = attributes.get("duration").and_then(|d| d.parse().ok())
+                        .unwrap_or(anim::DEFAULT_DURATION_MS);
+                    let event = attributes.get("event").cloned();
+                    let mut glyph = Glyph::with_lods(key.clone(), lods, &metrics, attributes);
+                    // Relative to the origin, like the meshes
+                    let to_origin = Affine::translate(-origin);
+                    glyph.paths = paths.iter().map(|path| path.transformed(to_origin)).collect();
+                    let id = self.insert(&key, glyph);
+                    if let Some(split) = key.rfind(FRAME_SEPARATOR) {
+                        if let Ok(n) = key[split + 1..].parse() {
+                            frames.entry(key[..split].to_owned()).or_insert_with(Vec::new)
+                                .push((n, id, duration, event));
+                        }
+                    }
+                    loaded.insert(key);
                 }
             }
         }
+
+        for (name, mut series) in frames.into_iter() {
+            series.sort_by_key(|(n, _, _, _)| *n);
+            let clip = Clip{
+                frames: series.iter()
+                    .map(|(_, id, duration, event)| Frame::new(*id, *duration, event.as_ref().map(|e| self.intern(e))))
+                    .collect(),
+            };
+            // Without a glyph of its own, the name draws the first frame
+            if !loaded.contains(&name) {
+                let first = self.get_id(clip.frames[0].glyph).clone();
+                self.insert(&name, first);
+            }
+            match self.clip_ids.get(&name).cloned() {
+                Some(id) => self.clips[id.0] = clip,
+                None => {
+                    self.clip_ids.insert(name, ClipId(self.clips.len()));
+                    self.clips.push(clip);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clip(&self, name: &str) -> Option<ClipId> {
+        self.clip_ids.get(name).cloned()
+    }
+
+    pub fn get_clip(&self, id: ClipId) -> &Clip {
+        &self.clips[id.0]
+    }
+
+    fn intern(&mut self, name: &str) -> &'static str {
+        match self.events.get(name) {
+            Some(interned) => interned,
+            None => {
+                let interned: &'static str = Box::leak(name.to_owned().into_boxed_str());
+                self.events.insert(interned);
+                interned
+            },
+        }
     }
 }
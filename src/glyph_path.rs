@@ -0,0 +1,254 @@
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, GpuTriangle, Mesh, ShapeRenderer, Vertex},
+    lyon::{
+        math::Point,
+        path::{Path, PathEvent},
+        tessellation::{FillOptions, FillTessellator, StrokeOptions, StrokeTessellator},
+    },
+};
+
+use usvg;
+
+use crate::affine::Affine;
+use crate::clip::clip_mesh;
+use crate::gradient::Gradient;
+use crate::morph;
+use crate::path_convert::{convert_path, convert_path_with};
+
+// Paths with neither a fill nor a stroke are filled with this rather than
+// silently disappearing
+pub const DEFAULT_COLOR: Color = Color{ r: 0.6, g: 0.9, b: 0.6, a: 1.0};
+
+// Copies the triangles of `src` to the end of `dest`
+pub fn append_mesh(src: &Mesh, dest: &mut Mesh) {
+    let offset = dest.vertices.len() as u32;
+    dest.vertices.extend(src.vertices.iter()
+        .map(|v| Vertex{pos: v.pos, col: v.col, tex_pos: v.tex_pos}));
+    dest.triangles.extend(src.triangles.iter()
+        .map(|t| GpuTriangle{
+            z:t.z,
+            indices:[t.indices[0]+offset, t.indices[1]+offset, t.indices[2]+offset],
+            image: t.image.clone()}));
+}
+
+// The smallest Rectangle around all the points
+pub fn points_bounds<'a, I: IntoIterator<Item = &'a Vector>>(points: I) -> Option<Rectangle> {
+    let mut points = points.into_iter();
+    let first = *points.next()?;
+    let (min, max) = points.fold((first, first), |(min, max), p| (
+        Vector{ x: min.x.min(p.x), y: min.y.min(p.y) },
+        Vector{ x: max.x.max(p.x), y: max.y.max(p.y) },
+    ));
+    Some(Rectangle{ pos: min, size: max - min })
+}
+
+fn transform_point(transform: Affine, p: Point) -> Point {
+    let v = transform * Vector{ x: p.x, y: p.y };
+    Point::new(v.x, v.y)
+}
+
+pub fn build_path<I: IntoIterator<Item = PathEvent>>(events: I) -> Path {
+    let mut builder = Path::builder();
+    for event in events {
+        match event {
+            PathEvent::MoveTo(to) => builder.move_to(to),
+            PathEvent::Line(line) => builder.line_to(line.to),
+            PathEvent::Quadratic(q) => builder.quadratic_bezier_to(q.ctrl, q.to),
+            PathEvent::Cubic(c) => builder.cubic_bezier_to(c.ctrl1, c.ctrl2, c.to),
+            PathEvent::Close(_) => builder.close(),
+        }
+    }
+    builder.build()
+}
+
+#[derive(Clone)]
+pub enum Paint {
+    Color(Color),
+    Gradient(Gradient),
+}
+
+impl Paint {
+    fn from_usvg(rtree: &usvg::Tree, paint: &usvg::Paint, opacity: f32, bounds: Rectangle, to_user: Affine) -> Paint {
+        match paint {
+            usvg::Paint::Color(col) => Paint::Color(Color::from_rgba(col.red, col.green, col.blue, opacity)),
+            usvg::Paint::Link(ref id) => rtree.defs_by_id(id)
+                .and_then(|n| Gradient::from_node(&n.borrow(), opacity, bounds))
+                .map(|g| Paint::Gradient(g.transformed(to_user)))
+                .unwrap_or(Paint::Color(DEFAULT_COLOR)),
+        }
+    }
+
+    pub fn color_at(&self, pos: Vector) -> Color {
+        match self {
+            Paint::Color(color) => *color,
+            Paint::Gradient(gradient) => gradient.color_at(pos),
+        }
+    }
+
+    fn transformed(&self, to_old: Affine) -> Paint {
+        match self {
+            Paint::Color(color) => Paint::Color(*color),
+            Paint::Gradient(gradient) => Paint::Gradient(gradient.transformed(to_old)),
+        }
+    }
+
+    // Colours the triangles of `flat` into `dest`
+    fn fill(&self, flat: &Mesh, dest: &mut Mesh) {
+        match self {
+            Paint::Color(color) => {
+                let offset = dest.vertices.len();
+                append_mesh(flat, dest);
+                for v in dest.vertices[offset..].iter_mut() {
+                    v.col = *color;
+                }
+            },
+            Paint::Gradient(gradient) => gradient.paint(flat, dest),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Stroke {
+    pub paint: Paint,
+    pub width: f32,
+}
+
+// One path of a glyph as it is in the SVG, in the glyph's own space, so
+// the glyph can be tessellated again without the SVG
+#[derive(Clone)]
+pub struct GlyphPath {
+    pub path: Path,
+    pub fill: Option<Paint>,
+    pub stroke: Option<Stroke>,
+    // Of the groups the path is in
+    pub opacity: f32,
+    // Convex polygons the path is clipped to
+    pub clips: Vec<Vec<Vector>>,
+}
+
+impl GlyphPath {
+    // `transform` takes the path's own space to the glyph's
+    pub fn from_usvg(rtree: &usvg::Tree, p: &usvg::Path, transform: Affine, opacity: f32, clips: Vec<Vec<Vector>>) -> GlyphPath {
+        // Gradients are defined in the path's own space, and against its
+        // bounds there
+        let ends: Vec<Vector> = convert_path(p)
+            .map(|event| match event {
+                PathEvent::MoveTo(to) => to,
+                PathEvent::Line(line) | PathEvent::Close(line) => line.to,
+                PathEvent::Quadratic(q) => q.to,
+                PathEvent::Cubic(c) => c.to,
+            })
+            .map(|p| Vector{ x: p.x, y: p.y })
+            .collect();
+        let bounds = points_bounds(ends.iter())
+            .unwrap_or(Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: 0.0, y: 0.0 }));
+        let to_user = transform.inverse().unwrap_or(Affine::IDENTITY);
+        let paint = |paint: &usvg::Paint, opacity: f64|
+            Paint::from_usvg(rtree, paint, opacity as f32, bounds, to_user);
+
+        let fill = p.fill.as_ref().map(|f| paint(&f.paint, f.opacity.value()));
+        let stroke = p.stroke.as_ref().map(|s| Stroke{
+            paint: paint(&s.paint, s.opacity.value()),
+            width: s.width.value() as f32 * transform.max_scale(),
+        });
+        GlyphPath{
+            path: build_path(convert_path_with(p, Some(transform))),
+            fill: if fill.is_none() && stroke.is_none() { Some(Paint::Color(DEFAULT_COLOR)) } else { fill },
+            stroke: stroke,
+            opacity: opacity,
+            clips: clips,
+        }
+    }
+
+    pub fn transformed(&self, transform: Affine) -> GlyphPath {
+        let to_old = transform.inverse().unwrap_or(Affine::IDENTITY);
+        GlyphPath{
+            path: build_path(self.path.iter().map(|event| match event {
+                PathEvent::MoveTo(to) => PathEvent::MoveTo(transform_point(transform, to)),
+                PathEvent::Line(mut line) => {
+                    line.from = transform_point(transform, line.from);
+                    line.to = transform_point(transform, line.to);
+                    PathEvent::Line(line)
+                },
+                PathEvent::Quadratic(mut q) => {
+                    q.from = transform_point(transform, q.from);
+                    q.ctrl = transform_point(transform, q.ctrl);
+                    q.to = transform_point(transform, q.to);
+                    PathEvent::Quadratic(q)
+                },
+                PathEvent::Cubic(mut c) => {
+                    c.from = transform_point(transform, c.from);
+                    c.ctrl1 = transform_point(transform, c.ctrl1);
+                    c.ctrl2 = transform_point(transform, c.ctrl2);
+                    c.to = transform_point(transform, c.to);
+                    PathEvent::Cubic(c)
+                },
+                PathEvent::Close(mut line) => {
+                    line.from = transform_point(transform, line.from);
+                    line.to = transform_point(transform, line.to);
+                    PathEvent::Close(line)
+                },
+            })),
+            fill: self.fill.as_ref().map(|f| f.transformed(to_old)),
+            stroke: self.stroke.as_ref().map(|s| Stroke{
+                paint: s.paint.transformed(to_old),
+                width: s.width * transform.max_scale(),
+            }),
+            opacity: self.opacity,
+            clips: self.clips.iter()
+                .map(|clip| clip.iter().map(|p| transform * *p).collect())
+                .collect(),
+        }
+    }
+
+    // The fill and stroke as triangles, appended to `dest`. The tessellators
+    // are shared between paths to reuse their buffers.
+    pub fn tessellate(&self, tolerance: f32, fill: &mut FillTessellator, stroke: &mut StrokeTessellator, dest: &mut Mesh) {
+        let mut local = Mesh::new();
+        if let Some(ref paint) = self.fill {
+            let mut flat = Mesh::new();
+            let filled = fill.tessellate_path(
+                self.path.iter(),
+                &FillOptions::tolerance(tolerance),
+                &mut ShapeRenderer::new(&mut flat, Color::WHITE));
+            // A path lyon can't fill, say from a bad edit to the sheet, is
+            // left unfilled rather than taking the game down
+            if filled.is_ok() {
+                paint.fill(&flat, &mut local);
+            }
+        }
+        if let Some(ref paint) = self.stroke {
+            let mut flat = Mesh::new();
+            self.stroke_into(paint.width, tolerance, stroke, &mut flat);
+            paint.paint.fill(&flat, &mut local);
+        }
+        for v in local.vertices.iter_mut() {
+            v.col.a *= self.opacity;
+        }
+        if self.clips.is_empty() {
+            append_mesh(&local, dest);
+        } else {
+            clip_mesh(&local, &self.clips, dest);
+        }
+    }
+
+    // The path's outline `width` wide, in white, whatever its own stroke
+    pub fn stroke_into(&self, width: f32, tolerance: f32, tessellator: &mut StrokeTessellator, dest: &mut Mesh) {
+        let (vertices, triangles) = (dest.vertices.len(), dest.triangles.len());
+        let stroked = tessellator.tessellate_path(
+            self.path.iter(),
+            &StrokeOptions::tolerance(tolerance).with_line_width(width),
+            &mut ShapeRenderer::new(dest, Color::WHITE));
+        // Leaves out a stroke that failed part way rather than half of it
+        if stroked.is_err() {
+            dest.vertices.truncate(vertices);
+            dest.triangles.truncate(triangles);
+        }
+    }
+
+    // The closed contours of the path, with curves cut into lines
+    pub fn contours(&self) -> Vec<Vec<Vector>> {
+        morph::flatten(self.path.iter())
+    }
+}
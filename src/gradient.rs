@@ -0,0 +1,278 @@
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, GpuTriangle, Mesh, Vertex},
+};
+
+use usvg;
+
+use crate::affine::Affine;
+
+// Largest per-channel error allowed between the real gradient and the
+// linearly interpolated vertex colours before a triangle gets split
+const COLOR_TOLERANCE: f32 = 1.0/64.0;
+const MAX_SUBDIVISIONS: usize = 5;
+
+#[derive(Clone)]
+pub enum Shape {
+    Linear{ from: Vector, to: Vector },
+    Radial{ center: Vector, focus: Vector, radius: f32 },
+}
+
+#[derive(Clone)]
+pub struct Gradient {
+    shape: Shape,
+    stops: Vec<(f32, Color)>,
+    spread: usvg::SpreadMethod,
+    // Maps user space into the gradient's own coordinate system
+    to_gradient: Affine,
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+    Color{
+        r: a.r + (b.r - a.r) * t,
+        g: a.g + (b.g - a.g) * t,
+        b: a.b + (b.b - a.b) * t,
+        a: a.a + (b.a - a.a) * t,
+    }
+}
+
+fn color_error(a: Color, b: Color) -> f32 {
+    (a.r - b.r).abs()
+        .max((a.g - b.g).abs())
+        .max((a.b - b.b).abs())
+        .max((a.a - b.a).abs())
+}
+
+fn point(x: f64, y: f64) -> Vector {
+    Vector{ x: x as f32, y: y as f32 }
+}
+
+impl Gradient {
+    // `bounds` is the bounding box of the filled path, needed for
+    // gradients using objectBoundingBox units
+    pub fn from_node(kind: &usvg::NodeKind, opacity: f32, bounds: Rectangle) -> Option<Gradient> {
+        let (shape, base) = match kind {
+            usvg::NodeKind::LinearGradient(ref g) => (
+                Shape::Linear{ from: point(g.x1, g.y1), to: point(g.x2, g.y2) },
+                &g.base
+            ),
+            usvg::NodeKind::RadialGradient(ref g) => (
+                Shape::Radial{ center: point(g.cx, g.cy), focus: point(g.fx, g.fy), radius: g.r as f32 },
+                &g.base
+            ),
+            _ => return None,
+        };
+        if base.stops.is_empty() {
+            return None;
+        }
+
+        let mut to_user = Affine::from_usvg(&base.transform);
+        if base.units == usvg::Units::ObjectBoundingBox {
+            to_user = Affine::translate(bounds.pos) * Affine::scale(bounds.size) * to_user;
+        }
+
+        let stops = base.stops.iter()
+            .map(|s| (s.offset.value() as f32, Color::from_rgba(
+                s.color.red,
+                s.color.green,
+                s.color.blue,
+                s.opacity.value() as f32 * opacity
+            )))
+            .collect();
+
+        Some(Gradient{
+            shape: shape,
+            stops: stops,
+            spread: base.spread_method,
+            to_gradient: to_user.inverse()?,
+        })
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    // Offsets from 0 to 1, in order
+    pub fn stops(&self) -> &[(f32, Color)] {
+        &self.stops
+    }
+
+    pub fn spread(&self) -> usvg::SpreadMethod {
+        self.spread
+    }
+
+    pub fn to_gradient(&self) -> Affine {
+        self.to_gradient
+    }
+
+    fn offset_at(&self, pos: Vector) -> f32 {
+        let p = self.to_gradient * pos;
+        match self.shape {
+            Shape::Linear{ from, to } => {
+                let dir = to - from;
+                let len2 = dir.dot(dir);
+                if len2 > 0.0 { (p - from).dot(dir) / len2 } else { 1.0 }
+            },
+            Shape::Radial{ center, focus, radius } => {
+                // Solve for the circle, interpolated between the focus and
+                // the outer circle, that passes through `p`
+                let d = p - focus;
+                let cd = center - focus;
+                let a = cd.dot(cd) - radius * radius;
+                let b = d.dot(cd);
+                let c = d.dot(d);
+                if a.abs() < std::f32::EPSILON {
+                    if b.abs() < std::f32::EPSILON { 1.0 } else { c / (2.0 * b) }
+                } else {
+                    (b - (b * b - a * c).max(0.0).sqrt()) / a
+                }
+            },
+        }
+    }
+
+    // The same gradient for points in another space, `to_user` taking
+    // them into this one's user space
+    pub fn transformed(&self, to_user: Affine) -> Gradient {
+        Gradient{ to_gradient: self.to_gradient * to_user, ..self.clone() }
+    }
+
+    pub fn color_at(&self, pos: Vector) -> Color {
+        let t = self.offset_at(pos);
+        let t = match self.spread {
+            usvg::SpreadMethod::Pad => t,
+            usvg::SpreadMethod::Repeat => t - t.floor(),
+            usvg::SpreadMethod::Reflect => {
+                let m = t - 2.0 * (t / 2.0).floor();
+                if m > 1.0 { 2.0 - m } else { m }
+            },
+        };
+
+        let (first_offset, first) = self.stops[0];
+        if t <= first_offset {
+            return first;
+        }
+        for pair in self.stops.windows(2) {
+            let (o0, c0) = pair[0];
+            let (o1, c1) = pair[1];
+            if t <= o1 {
+                return if o1 > o0 { lerp(c0, c1, (t - o0) / (o1 - o0)) } else { c1 };
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+
+    // Copies the triangles of `src` into `dest`, colouring every vertex from
+    // the gradient and splitting triangles the vertex colours can't follow
+    pub fn paint(&self, src: &Mesh, dest: &mut Mesh) {
+        for t in src.triangles.iter() {
+            let corners = [
+                src.vertices[t.indices[0] as usize].pos,
+                src.vertices[t.indices[1] as usize].pos,
+                src.vertices[t.indices[2] as usize].pos,
+            ];
+            self.paint_triangle(corners, t.z, dest, 0);
+        }
+    }
+
+    fn paint_triangle(&self, p: [Vector; 3], z: f32, dest: &mut Mesh, depth: usize) {
+        let c = [self.color_at(p[0]), self.color_at(p[1]), self.color_at(p[2])];
+        let m = [(p[0] + p[1]) / 2.0, (p[1] + p[2]) / 2.0, (p[2] + p[0]) / 2.0];
+
+        if depth < MAX_SUBDIVISIONS {
+            let error = color_error(self.color_at(m[0]), lerp(c[0], c[1], 0.5))
+                .max(color_error(self.color_at(m[1]), lerp(c[1], c[2], 0.5)))
+                .max(color_error(self.color_at(m[2]), lerp(c[2], c[0], 0.5)));
+            if error > COLOR_TOLERANCE {
+                self.paint_triangle([p[0], m[0], m[2]], z, dest, depth + 1);
+                self.paint_triangle([m[0], p[1], m[1]], z, dest, depth + 1);
+                self.paint_triangle([m[2], m[1], p[2]], z, dest, depth + 1);
+                self.paint_triangle([m[0], m[1], m[2]], z, dest, depth + 1);
+                return;
+            }
+        }
+
+        let offset = dest.vertices.len() as u32;
+        for i in 0..3 {
+            dest.vertices.push(Vertex{ pos: p[i], col: c[i], tex_pos: None });
+        }
+        dest.triangles.push(GpuTriangle{
+            z: z,
+            indices: [offset, offset + 1, offset + 2],
+            image: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Black at 0 to white at 10 along x
+    fn linear(spread: usvg::SpreadMethod) -> Gradient {
+        Gradient{
+            shape: Shape::Linear{ from: point(0.0, 0.0), to: point(10.0, 0.0) },
+            stops: vec![(0.0, Color::BLACK), (1.0, Color::WHITE)],
+            spread: spread,
+            to_gradient: Affine::IDENTITY,
+        }
+    }
+
+    fn grey(gradient: &Gradient, x: f32) -> f32 {
+        gradient.color_at(Vector{ x: x, y: 3.0 }).r
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn linear_offsets_run_past_the_ends() {
+        let gradient = linear(usvg::SpreadMethod::Pad);
+        assert_near(gradient.offset_at(Vector{ x: 0.0, y: 5.0 }), 0.0);
+        assert_near(gradient.offset_at(Vector{ x: 2.5, y: -5.0 }), 0.25);
+        assert_near(gradient.offset_at(Vector{ x: 12.0, y: 0.0 }), 1.2);
+        assert_near(gradient.offset_at(Vector{ x: -3.0, y: 0.0 }), -0.3);
+    }
+
+    #[test]
+    fn pad_clamps_to_the_end_stops() {
+        let gradient = linear(usvg::SpreadMethod::Pad);
+        assert_near(grey(&gradient, 5.0), 0.5);
+        assert_near(grey(&gradient, 12.0), 1.0);
+        assert_near(grey(&gradient, -3.0), 0.0);
+    }
+
+    #[test]
+    fn repeat_starts_over() {
+        let gradient = linear(usvg::SpreadMethod::Repeat);
+        assert_near(grey(&gradient, 5.0), 0.5);
+        assert_near(grey(&gradient, 12.0), 0.2);
+        assert_near(grey(&gradient, -3.0), 0.7);
+    }
+
+    #[test]
+    fn reflect_mirrors_every_other_run() {
+        let gradient = linear(usvg::SpreadMethod::Reflect);
+        assert_near(grey(&gradient, 5.0), 0.5);
+        assert_near(grey(&gradient, 12.0), 0.8);
+        assert_near(grey(&gradient, -3.0), 0.3);
+        assert_near(grey(&gradient, 23.0), 0.3);
+    }
+
+    #[test]
+    fn radial_offsets_grow_from_the_focus() {
+        let gradient = Gradient{
+            shape: Shape::Radial{ center: point(0.0, 0.0), focus: point(0.0, 0.0), radius: 10.0 },
+            ..linear(usvg::SpreadMethod::Pad)
+        };
+        assert_near(gradient.offset_at(Vector{ x: 0.0, y: 0.0 }), 0.0);
+        assert_near(gradient.offset_at(Vector{ x: 5.0, y: 0.0 }), 0.5);
+        assert_near(gradient.offset_at(Vector{ x: 0.0, y: -10.0 }), 1.0);
+    }
+
+    #[test]
+    fn transformed_gradients_follow_their_points() {
+        let gradient = linear(usvg::SpreadMethod::Pad).transformed(Affine::translate(Vector{ x: 5.0, y: 0.0 }));
+        assert_near(grey(&gradient, 0.0), 0.5);
+    }
+}
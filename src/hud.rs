@@ -0,0 +1,61 @@
+use quicksilver::{
+    geom::{Transform, Vector},
+    graphics::{Background::Col, Color},
+    lifecycle::Window,
+};
+
+use crate::glyph::{Glyph, GlyphSet};
+use crate::text::TextLayout;
+
+// Drawn on top of everything in the world
+pub const HUD_Z: f32 = 100.0;
+
+// A piece of text whose mesh is kept between frames and only rebuilt when
+// the text changes
+pub struct Label {
+    pub layout: TextLayout,
+    pub pos: Vector,
+    pub color: Color,
+    pub visible: bool,
+    text: String,
+    glyph: Option<Glyph>,
+}
+
+impl Label {
+    pub fn new(layout: TextLayout, pos: Vector) -> Label {
+        Label{
+            layout: layout,
+            pos: pos,
+            color: Color::WHITE,
+            visible: true,
+            text: String::new(),
+            glyph: None,
+        }
+    }
+
+    // Builds the mesh again on the next update, after the glyphs changed
+    pub fn invalidate(&mut self) {
+        self.glyph = None;
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if text != self.text {
+            self.text.clear();
+            self.text.push_str(text);
+            self.glyph = None;
+        }
+    }
+
+    // Rebuilds the mesh if the text changed
+    pub fn update(&mut self, glyphs: &GlyphSet) {
+        if self.visible && self.glyph.is_none() {
+            self.glyph = Some(self.layout.build(&self.text, Col(Color::WHITE), glyphs));
+        }
+    }
+
+    pub fn draw(&self, window: &mut Window, z: f32) {
+        if let (true, Some(glyph)) = (self.visible, self.glyph.as_ref()) {
+            window.draw_ex(glyph, Col(self.color), Transform::translate(self.pos), z);
+        }
+    }
+}
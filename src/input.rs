@@ -11,6 +11,12 @@ pub struct Input {
     pub quit: bool,
 }
 
+impl Input {
+    pub fn none() -> Input {
+        Input{ x: 0.0, y: 0.0, shoot: false, quit: false }
+    }
+}
+
 pub fn get_input(window: &Window, mouse_pressed: bool, position: Vector) -> Input {
     let mut x = 0.0;
     let mut y = 0.0;
@@ -1,78 +1,193 @@
 extern crate quicksilver;
 extern crate usvg;
 extern crate rand;
+#[cfg(target_arch = "wasm32")]
+#[macro_use]
+extern crate stdweb;
+mod affine;
+mod anim;
+mod capture;
+#[cfg(feature = "count-allocations")]
+mod alloc_count;
+mod charmap;
+mod clip;
+mod clock;
+mod collide;
+mod console;
+mod debug;
+#[cfg(test)]
+mod fingerprint;
+mod gradient;
+mod components;
 mod path_convert;
+mod raster;
+mod png;
+mod profile;
 mod glyph;
+mod glyph_path;
+mod hud;
 mod input;
+mod markup;
+mod morph;
 mod sprite;
-
-use rand::Rng;
-use glyph::{Glyph, GlyphSet};
-use sprite::Sprite;
+mod svg_meta;
+mod svg_export;
+mod systems;
+mod text;
+mod tween;
+mod world;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use anim::ClipId;
+use capture::Recording;
+use charmap::Fallback;
+use components::Spawner;
+use console::{Command, Console};
+use debug::Overlay;
+use morph::Morph;
+use profile::{Format, Profiler};
+use raster::Raster;
+use glyph::{Glyph, GlyphId, GlyphSet};
+use hud::Label;
+use text::TextLayout;
+use tween::Tween;
+use world::{Entity, World};
 use quicksilver::{
     Result,
-    geom::{Shape, Transform, Vector},
-    graphics::{Background::Col, Color, ResizeStrategy},
+    geom::{Rectangle, Transform, Vector},
+    graphics::{Background::Col, Color, Drawable, Mesh, ResizeStrategy},
     input::{Key, ButtonState,},
     lifecycle::{Event, Settings, State, Window, run},
 };
 
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: alloc_count::Counting = alloc_count::Counting;
+
 const BG_COLOR: Color = Color{ r: 0.2, g: 0.1, b: 0.2, a: 1.0};
-const FG_COLOR: Color = Color{ r: 0.6, g: 0.2, b: 0.6, a: 1.0};
 const WIDTH: f32 = 1000.0;
 const HEIGHT: f32 = 1000.0;
+// Background triangles are drawn as small as 0.2, text at 0.12
+const GLYPH_TOLERANCES: [f32; 4] = [0.1, 0.3, 0.8, 2.0];
+// Where F5 reloads the glyphs from
+#[cfg(not(target_arch = "wasm32"))]
+const GLYPHS_PATH: &str = "assets/glyphs.svg";
+// Laid out in each alignment by `--export-svg` and `--render`, to check
+// text by eye
+#[cfg(not(target_arch = "wasm32"))]
+const SPECIMEN: &str = "Your [color=#f66]life[/color] is {big}currency{/big}.\n[icon=coin] ludum dare [set=old_][icon=hero]";
+// Of the glyph sheet drawn by `--render`, and around it
+#[cfg(not(target_arch = "wasm32"))]
+const RENDER_WIDTH: f32 = 1200.0;
+#[cfg(not(target_arch = "wasm32"))]
+const RENDER_MARGIN: f32 = 20.0;
+// Floats up from enemies as they are hit
+const POPUP: &str = "[color=#ffcc66]pop!";
+
+// Glyphs the game spawns, looked up once at load time
+struct Ids {
+    coin: GlyphId,
+    coin_spin: Option<ClipId>,
+    popup: GlyphId,
+}
 
 struct GameWindow {
     glyphs: GlyphSet,
-    sprites: Vec<Sprite>,
-    hero: Glyph,
-    pos: Vector,
-    speed: Vector,
-    triangle_cooldown: usize,
-    cooldown: usize,
-    mouse_pos: Vector,
-    mouse_cooldown: usize,
+    ids: Ids,
+    world: World,
+    morphs: Morph,
+    hero: Entity,
+    // Circling the hero, from the `shields` console command
+    shields: Vec<Entity>,
+    fps_label: Entity,
     mouse_pressed: bool,
-    scale: Vector,
     frame: usize,
-    show_fps: bool,
-    tri_count: usize,
-    tri_count_max: usize,
+    overlay: Overlay,
+    profiler: Profiler,
+    // Where to write the profile on exit
+    profile_path: Option<String>,
+    // Where to write the last frame drawn on exit, and a copy of it
+    frame_dir: Option<String>,
+    last_frame: Mesh,
+    console: Console,
+    rng: StdRng,
+    seed: u64,
+    // Shooting is free
+    god: bool,
+    // Multiplies how far everything moves each frame
+    time_scale: f32,
+    // Allocation count at the start of the last frame
+    allocations: usize,
+    // F8 saves the next frame drawn
+    screenshot: bool,
+    // Frames are recorded while F9 is held
+    recording: Recording,
+    record: bool,
 }
 
 impl State for GameWindow {
     fn new() -> Result<GameWindow> {
-        let mut glyphs = GlyphSet::new().unwrap();
-        glyphs.load_from_svg_bytes(include_bytes!("../assets/glyphs.svg"));
-
-        let mut sprites = Vec::new();
+        let mut glyphs = load_glyphs(include_bytes!("../assets/glyphs.svg")).unwrap();
+        let popup = popup_glyph(&glyphs);
+        let ids = Ids{
+            coin: glyphs.id(glyph::COIN),
+            coin_spin: glyphs.clip(glyph::COIN),
+            popup: glyphs.insert("popup", popup),
+        };
 
+        let mut world = World::new();
+        systems::spawn_cursor(&mut world, glyphs.id("cursor"));
+        let hero = systems::spawn_hero(
+            &mut world,
+            glyphs.id(glyph::HERO),
+            glyphs.clip(glyph::HERO),
+            glyphs.id(glyph::COIN),
+            Vector{x: WIDTH/2.0 - 350.0, y: HEIGHT/2.0 - 50.0},
+            Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: WIDTH, y: HEIGHT })
+        );
         {
-            let mut cursor = Sprite::new(
-                "cursor",
-                Vector{ x: -1000.0, y: -1000.0 }
-            );
-            cursor.visible = false;
-            cursor.z = 10.0;
-            sprites.push(cursor);
+            let spawner = world.spawn();
+            world.spawners.insert(spawner, Spawner::new(glyphs.id("triangle"), glyphs.clip("triangle"), 1.0));
         }
 
+        export_svg(&glyphs);
+        render_png(&glyphs);
+
+        let seed = rand::thread_rng().gen();
+
+        let mut profiler = Profiler::new();
+        let profile_path = profile_path();
+        if profile_path.is_some() {
+            profiler.record();
+        }
+
+        let fps_label = world.spawn();
+        world.labels.insert(fps_label, Label::new(TextLayout::new(12.0), Vector{ x: 10.0, y: 10.0 }));
+
         Ok(GameWindow{
-            pos: Vector{x: WIDTH/2.0 - 350.0, y: HEIGHT/2.0 - 50.0},
-            speed: Vector{x: 0.0, y: 0.0},
-            hero: glyphs.get(glyph::HERO).clone(),
             glyphs: glyphs,
-            sprites: sprites,
-            cooldown: 0,
-            triangle_cooldown: 0,
-            mouse_pos: Vector{ x: 0.0, y: 0.0 },
-            mouse_cooldown: 0,
+            ids: ids,
+            world: world,
+            morphs: Morph::new(),
+            hero: hero,
+            shields: Vec::new(),
+            fps_label: fps_label,
             mouse_pressed: false,
-            scale: Vector{x: 1.0, y: 1.0},
             frame: 0,
-            show_fps: true,
-            tri_count: 0,
-            tri_count_max: 0,
+            overlay: Overlay::new(),
+            profiler: profiler,
+            profile_path: profile_path,
+            frame_dir: export_dir(),
+            last_frame: Mesh::new(),
+            console: Console::new(WIDTH),
+            rng: StdRng::seed_from_u64(seed),
+            seed: seed,
+            god: false,
+            time_scale: 1.0,
+            allocations: allocations().unwrap_or(0),
+            screenshot: false,
+            recording: Recording::new(),
+            record: false,
         })
     }
 
@@ -89,179 +204,435 @@ impl State for GameWindow {
             _ => {}
         };
 
+        if let Event::Closed = event {
+            self.save_profile();
+            self.save_frame();
+        }
+
+        if let Some(command) = self.console.event(event) {
+            self.run(command);
+        }
+
         Ok(())
     }
 
     fn update(&mut self, window: &mut Window) -> Result<()> {
-        if self.speed.x.abs() > 0.0 || self.speed.y.abs() > 0.0 {
-            self.speed *= 0.9;
-            if self.speed.x.abs() < 0.1 {
-                self.speed.x = 0.0;
+        self.profiler.frame();
+        if let Some(count) = allocations() {
+            self.overlay.stats.allocations = Some(count - self.allocations);
+            self.allocations = count;
+        }
+        self.profiler.begin("update");
+        self.profiler.begin("input");
+        // Typing into the console shouldn't also play the game
+        let input = if self.console.open {
+            input::Input::none()
+        } else {
+            match window.keyboard()[Key::Tab] {
+                ButtonState::Pressed => {
+                    self.overlay.shapes = !self.overlay.shapes;
+                },
+                _ => ()
             }
-            if self.speed.y.abs() < 0.1 {
-                self.speed.y = 0.0;
+
+            match window.keyboard()[Key::F] {
+                ButtonState::Pressed => {
+                    self.overlay.visible = !self.overlay.visible;
+                },
+                _ => ()
             }
-        }
-        match window.keyboard()[Key::Tab] {
-            ButtonState::Pressed => {
-                //println!("View:          {:?}", window.view());
-                //println!("Screen Size:   {:?}", window.screen_size());
-            },
-            _ => ()
-        }
 
-        match window.keyboard()[Key::F] {
-            ButtonState::Pressed => {
-                self.show_fps = if self.show_fps { false } else { true };
-            },
-            _ => ()
-        }
+            match window.keyboard()[Key::F5] {
+                ButtonState::Pressed => self.reload_glyphs(),
+                _ => ()
+            }
 
-        let input = input::get_input(&window, self.mouse_pressed, self.pos);
+            match window.keyboard()[Key::F8] {
+                ButtonState::Pressed => self.screenshot = true,
+                _ => ()
+            }
+            self.record = window.keyboard()[Key::F9].is_down();
 
-        self.speed.x += 2.5 * input.x;
-        self.speed.y += 2.5 * input.y;
+            let hero_pos = self.world.positions.get(self.hero).cloned().unwrap_or(Vector{ x: 0.0, y: 0.0 });
+            input::get_input(&window, self.mouse_pressed, hero_pos)
+        };
 
         if input.quit {
+            self.save_profile();
             window.close();
         }
+        systems::friction(&mut self.world);
+        systems::steer(&mut self.world, &input);
+        self.profiler.end("input");
+
+        self.profiler.begin("ttl");
+        systems::lifetime(&mut self.world, self.time_scale);
+        self.world.maintain();
+        self.profiler.end("ttl");
+
+        self.profiler.begin("movement");
+        systems::movement(&mut self.world, self.time_scale);
+        systems::spin(&mut self.world, self.time_scale);
+        systems::tweens(&mut self.world, self.time_scale);
+        systems::animate(&mut self.world, &self.glyphs, self.time_scale);
+        systems::face(&mut self.world);
+        systems::wrap(&mut self.world);
+        systems::cursor(&mut self.world, window.mouse().pos());
+        self.profiler.end("movement");
+
+        self.profiler.begin("spawning");
+        systems::spawn_enemies(&mut self.world, &mut self.rng, Vector{ x: WIDTH, y: HEIGHT }, self.time_scale);
+        systems::flash(&mut self.world, self.time_scale);
+        systems::shoot(&mut self.world, &input, window.mouse().pos(), self.god, self.ids.coin, self.ids.coin_spin, self.time_scale);
+        let mut detached = systems::transforms(&mut self.world);
+        systems::collide(&mut self.world, &self.glyphs);
+        systems::events(&mut self.world, self.ids.popup);
+        // Again for whatever the events spawned, so it's drawn in place
+        // this frame
+        detached += systems::transforms(&mut self.world);
+        if detached > 0 {
+            self.console.print(&format!("detached {} from parents that loop or nest too deep", detached));
+        }
+        self.profiler.end("spawning");
 
-        self.pos += self.speed;
-        if self.pos.x > WIDTH {
-            self.pos.x = 0.0;
-        } else if self.pos.x < 0.0 {
-            self.pos.x = WIDTH;
+        self.profiler.begin("text");
+        if let Some(label) = self.world.labels.get_mut(self.fps_label) {
+            label.visible = self.overlay.visible;
         }
-        if self.pos.y > HEIGHT {
-            self.pos.y = 0.0;
-        } else if self.pos.y < 0.0 {
-            self.pos.y = HEIGHT;
+        if self.overlay.visible {
+            if self.frame % 60 == 0 {
+                self.overlay.stats.fps = window.current_fps();
+                self.overlay.stats.average_fps = window.average_fps();
+                self.overlay.stats.entities = self.world.len();
+                self.overlay.stats.seed = self.seed;
+                let text = self.overlay.update_text(&self.profiler);
+                if let Some(label) = self.world.labels.get_mut(self.fps_label) {
+                    label.set_text(text);
+                }
+            }
+            if self.frame % 10 == 0 {
+                self.overlay.update_graph(&self.profiler);
+            }
+            self.frame += 1;
         }
+        systems::labels(&mut self.world, &self.glyphs);
+        self.console.update(&self.glyphs);
+        self.profiler.end("text");
 
-        self.sprites.retain(|s| (!s.use_ttl) || s.ttl > 0);
+        self.profiler.end("update");
+        Ok(())
+    }
 
-        for sprite in &mut self.sprites {
-            sprite.ttl -= 1;
-            sprite.move_by(sprite.speed);
-        }
+    fn draw(&mut self, window: &mut Window) -> Result<()> {
+        self.profiler.begin("draw");
+        window.clear(BG_COLOR)?;
 
-        if self.triangle_cooldown > 0 {
-            self.triangle_cooldown -= 1;
-        } else {
-            let mut rng = rand::thread_rng();
-            self.triangle_cooldown = 1;
-            self.sprites.push({
-                let mut sprite = Sprite::new("triangle", Vector{ x: rng.gen_range(0.0, WIDTH), y: -100.0 });
-                sprite.speed = Vector{ x: rng.gen_range(-5.0, 5.0), y: rng.gen_range(1.0, 5.0) };
-                sprite.use_ttl = true;
-                sprite.ttl = 600;
-                sprite.scale = Vector{x:1.0, y:1.0} * rng.gen_range(0.2, 1.0);
-                sprite.z = 1.0;
-                sprite
-            });
-            self.sprites.push({
-                let mut sprite = Sprite::new("triangle", Vector{ x: -100.0, y: rng.gen_range(0.0, HEIGHT) });
-                sprite.speed = Vector{ x: rng.gen_range(1.0, 5.0), y: rng.gen_range(1.0, 5.0) };
-                sprite.use_ttl = true;
-                sprite.ttl = 600;
-                sprite.scale = Vector{x:1.0, y:1.0} * rng.gen_range(0.2, 1.0);
-                sprite.z = 1.0;
-                sprite
-            });
-            self.sprites.push({
-                let mut sprite = Sprite::new("triangle", Vector{ x: WIDTH+100.0, y: rng.gen_range(0.0, HEIGHT) });
-                sprite.speed = Vector{ x: rng.gen_range(-5.0, -1.0), y: rng.gen_range(1.0, 5.0) };
-                sprite.use_ttl = true;
-                sprite.ttl = 600;
-                sprite.scale = Vector{x:1.0, y:1.0} * rng.gen_range(0.2, 1.0);
-                sprite.z = 1.0;
-                sprite
-            });
+        self.profiler.begin("sprites");
+        systems::draw_sprites(&self.world, &self.glyphs, &mut self.morphs, window);
+        self.profiler.end("sprites");
+
+        self.profiler.begin("hud");
+        systems::draw_labels(&self.world, window);
+        self.overlay.draw_graph(window);
+        self.overlay.draw_shapes(window, &self.world, &self.glyphs);
+        self.console.draw(window);
+        self.profiler.end("hud");
+
+        if self.overlay.visible {
+            self.overlay.stats.triangles = window.mesh().triangles.len();
+            self.overlay.stats.triangles_max = window.mesh().triangles.capacity();
+            self.overlay.stats.vertices = window.mesh().vertices.len();
         }
 
-        if self.cooldown > 0 {
-            self.cooldown -= 1;
-        } else if input.shoot {
-            self.scale *= 0.99;
-            self.cooldown = 6;
-            self.sprites.push({
-                let mut sprite = Sprite::new(glyph::COIN, Vector{ x: self.pos.x, y: self.pos.y - 50.0 });
-                sprite.speed = Vector{ x: self.speed.x, y: self.speed.y - 20.0 };
-                sprite.use_ttl = true;
-                sprite.ttl = 60;
-                sprite.scale = self.scale;
-                sprite.z = 1.0;
-                sprite
-            });
-        } else {
-            if self.scale.y < 1.0 {
-                self.scale *= 1.001;
+        self.profiler.begin("capture");
+        self.capture(window);
+        self.profiler.end("capture");
+        self.profiler.end("draw");
+
+        Ok(())
+    }
+}
+
+impl GameWindow {
+    // Saves a screenshot when asked for, and records a recording or encodes
+    // a frame of it. Grabbing a frame flushes the window's mesh, so this
+    // comes after everything else that reads it.
+    fn capture(&mut self, window: &mut Window) {
+        if self.frame_dir.is_some() {
+            self.last_frame.clear();
+            glyph_path::append_mesh(window.mesh(), &mut self.last_frame);
+        }
+        let view = Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: WIDTH, y: HEIGHT });
+        let mut saved = Vec::new();
+        let screenshot = self.screenshot;
+        let record = self.record && self.recording.tick();
+        self.screenshot = false;
+        if screenshot || record {
+            match capture::grab(window, view, BG_COLOR) {
+                Ok(frame) => {
+                    if screenshot {
+                        let name = capture::file_name("screenshot", self.seed);
+                        saved.push((capture::save(&name, &frame.to_png()), name));
+                    }
+                    if record {
+                        self.recording.record(&frame);
+                    }
+                },
+                Err(err) => self.console.print(&format!("could not capture the frame: {}", err)),
             }
         }
-
-        let mouse = window.mouse().pos();
-        if mouse != self.mouse_pos {
-            self.mouse_pos = mouse;
-            self.mouse_cooldown = 60;
-            self.sprites[0].move_to(Vector{ x: mouse.x, y: mouse.y });
-            self.sprites[0].visible = true;
-        } else {
-            if self.mouse_cooldown > 0 {
-                self.mouse_cooldown -= 1;
-            } else {
-                self.sprites[0].visible = false;
+        if !self.record && !self.recording.is_empty() {
+            if let Some(apng) = self.recording.encode() {
+                let name = capture::file_name("recording", self.seed);
+                saved.push((capture::save(&name, &apng), name));
             }
         }
-
-        if self.show_fps {
-            if self.frame % 60 == 0 {
-                self.glyphs.insert("fps", Glyph::from_text(format!(
-                    "fps: {:.0} / {:.0}\ntri: {} / {}",
-                    window.current_fps(),
-                    window.average_fps(),
-                    self.tri_count,
-                    self.tri_count_max
-                ), 12.0, Col(FG_COLOR), &self.glyphs));
-                self.sprites.push({
-                    let mut sprite = Sprite::new("fps", Vector{ x: 10.0, y: 10.0 });
-                    sprite.use_ttl = true;
-                    sprite.ttl = 60;
-                    sprite.z = 10.0;
-                    sprite
-                });
+        for (result, name) in saved {
+            match result {
+                Ok(()) => self.console.print(&format!("saved {}", name)),
+                Err(err) => self.console.print(&format!("could not save {}: {}", name, err)),
             }
-            self.frame += 1;
         }
+    }
 
-
-        Ok(())
+    // Picks up edits to the glyph sheet without restarting. Sprites keep
+    // their glyphs, as reloading doesn't change ids.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_glyphs(&mut self) {
+        let result = std::fs::read(GLYPHS_PATH)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| self.glyphs.load_from_svg_bytes(&bytes)
+                .map_err(|err| format!("{:?}", err)));
+        match result {
+            Ok(()) => {
+                // Built from the sheet's letters, so it's built again from
+                // the new ones under the same id
+                let popup = popup_glyph(&self.glyphs);
+                self.glyphs.insert("popup", popup);
+                self.morphs.clear();
+                self.overlay.clear_outlines();
+                for (_, label) in self.world.labels.iter_mut() {
+                    label.invalidate();
+                }
+                self.console.invalidate();
+                self.console.print("reloaded glyphs");
+            },
+            Err(err) => self.console.print(&format!("could not reload glyphs: {}", err)),
+        }
     }
 
-    fn draw(&mut self, window: &mut Window) -> Result<()> {
-        window.clear(BG_COLOR)?;
+    // The sheet is built into the wasm binary
+    #[cfg(target_arch = "wasm32")]
+    fn reload_glyphs(&mut self) {}
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_profile(&mut self) {
+        // Taken so quitting and then closing the window only writes once
+        if let Some(path) = self.profile_path.take() {
+            let report = self.profiler.report(Format::from_path(&path));
+            if let Err(err) = std::fs::write(&path, report) {
+                println!("Could not write profile to {}: {}", path, err);
+            }
+        }
+    }
 
-        for sprite in &self.sprites {
-            if sprite.visible {
-                window.draw_ex(
-                    self.glyphs.get(&sprite.glyph),
-                    Col(FG_COLOR),
-                    Transform::translate(sprite.bounds.center()) * Transform::scale(sprite.scale),
-                    sprite.z
-                );
+    // There's nowhere to write the profile to in the browser
+    #[cfg(target_arch = "wasm32")]
+    fn save_profile(&mut self) {}
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_frame(&mut self) {
+        if let Some(dir) = self.frame_dir.take() {
+            let view = Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: WIDTH, y: HEIGHT });
+            let path = std::path::Path::new(&dir).join("frame.svg");
+            if let Err(err) = std::fs::write(&path, svg_export::frame(&self.last_frame, view, BG_COLOR)) {
+                println!("Could not write {}: {}", path.display(), err);
             }
         }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn save_frame(&mut self) {}
+
+    fn run(&mut self, command: Command) {
+        match command {
+            Command::Spawn(glyph, count) => {
+                let id = self.glyphs.id(&glyph);
+                for _ in 0..count {
+                    let pos = Vector{ x: self.rng.gen_range(0.0, WIDTH), y: -100.0 };
+                    let velocity = Vector{ x: self.rng.gen_range(-5.0, 5.0), y: self.rng.gen_range(1.0, 5.0) };
+                    let scale = self.rng.gen_range(0.2, 1.0);
+                    let spin = self.rng.gen_range(-3.0, 3.0);
+                    systems::spawn_drifter(&mut self.world, id, pos, velocity, scale, spin);
+                }
+                self.console.print(&format!("spawned {} {}", count, glyph));
+            },
+            Command::God => {
+                self.god = !self.god;
+                self.console.print(if self.god { "god mode on" } else { "god mode off" });
+            },
+            Command::Shields => {
+                if self.shields.is_empty() {
+                    self.shields = systems::spawn_shields(&mut self.world, self.ids.coin, self.hero);
+                    self.console.print("shields on");
+                } else {
+                    self.world.despawned.extend(self.shields.drain(..));
+                    self.console.print("shields off");
+                }
+            },
+            Command::TimeScale(time_scale) => {
+                self.time_scale = time_scale;
+                self.console.print(&format!("timescale {}", time_scale));
+            },
+            Command::Seed(seed) => {
+                self.seed = seed;
+                self.rng = StdRng::seed_from_u64(seed);
+                self.console.print(&format!("seed {}", seed));
+            },
+            Command::Tween(property, frames, ease) => {
+                self.world.tweens.insert(self.hero, Tween::to(property, frames, ease));
+                self.console.print(&format!("tweening {:?} over {} frames", property, frames));
+            },
+        }
+    }
+}
+
+fn popup_glyph(glyphs: &GlyphSet) -> Glyph {
+    Glyph::from_text(POPUP.to_owned(), 24.0, Col(Color::WHITE), glyphs)
+}
+
+fn load_glyphs(bytes: &[u8]) -> std::result::Result<GlyphSet, String> {
+    let mut glyphs = GlyphSet::new().map_err(|err| format!("{:?}", err))?;
+    glyphs.set_tolerances(&GLYPH_TOLERANCES);
+    // The sheet only has lower case letters
+    glyphs.set_fallbacks(vec![
+        Fallback::StripAccent,
+        Fallback::Lowercase,
+        Fallback::Uppercase,
+        Fallback::Glyph(glyph::MISSING.to_owned()),
+    ]);
+    glyphs.load_from_svg_bytes(bytes).map_err(|err| format!("{:?}", err))?;
+    Ok(glyphs)
+}
+
+#[cfg(feature = "count-allocations")]
+fn allocations() -> Option<usize> {
+    Some(alloc_count::allocations())
+}
+
+#[cfg(not(feature = "count-allocations"))]
+fn allocations() -> Option<usize> {
+    None
+}
+
+// The value of a `--name=value` argument
+#[cfg(not(target_arch = "wasm32"))]
+fn arg(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args()
+        .find(|arg| arg.starts_with(&prefix))
+        .map(|arg| arg[prefix.len()..].to_owned())
+}
+
+// `--profile=report.csv` or `--profile=trace.json` writes timings on exit
+#[cfg(not(target_arch = "wasm32"))]
+fn profile_path() -> Option<String> {
+    arg("profile")
+}
 
-        window.draw_ex(&self.hero, Col(FG_COLOR), Transform::translate(self.pos) * Transform::scale(self.scale), 10);
+#[cfg(target_arch = "wasm32")]
+fn profile_path() -> Option<String> {
+    None
+}
+
+// See export_svg
+#[cfg(not(target_arch = "wasm32"))]
+fn export_dir() -> Option<String> {
+    arg("export-svg")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn export_dir() -> Option<String> {
+    None
+}
+
+// SPECIMEN wrapped and spaced as a title would be, aligned `align`
+#[cfg(not(target_arch = "wasm32"))]
+fn specimen(align: text::Align) -> TextLayout {
+    TextLayout::new(60.0)
+        .align(align)
+        .max_width(WIDTH * 0.6)
+        .letter_spacing(2.0)
+        .line_spacing(1.2)
+}
 
-        if self.show_fps {
-            self.tri_count = window.mesh().triangles.len();
-            self.tri_count_max = window.mesh().triangles.capacity();
+#[cfg(not(target_arch = "wasm32"))]
+const ALIGNMENTS: [(&str, text::Align); 3] = [
+    ("left", text::Align::Left),
+    ("center", text::Align::Center),
+    ("right", text::Align::Right),
+];
+
+// `--export-svg=dir` writes the glyph sheet, with and without its
+// triangles, and SPECIMEN in each alignment as SVG files into `dir`. The
+// last frame drawn is written there too on exit, as frame.svg.
+#[cfg(not(target_arch = "wasm32"))]
+fn export_svg(glyphs: &GlyphSet) {
+    let dir = match export_dir() {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => return,
+    };
+    let mut files = vec![
+        ("glyphs.svg".to_owned(), svg_export::glyph_sheet(glyphs, false, BG_COLOR)),
+        ("triangles.svg".to_owned(), svg_export::glyph_sheet(glyphs, true, BG_COLOR)),
+    ];
+    for (name, align) in ALIGNMENTS.iter() {
+        files.push((format!("text-{}.svg", name), svg_export::text(&specimen(*align), SPECIMEN, glyphs, false, BG_COLOR)));
+    }
+    for (name, svg) in files.iter() {
+        let path = dir.join(name);
+        if let Err(err) = std::fs::write(&path, svg) {
+            println!("Could not write {}: {}", path.display(), err);
         }
+    }
+}
 
-        Ok(())
+#[cfg(target_arch = "wasm32")]
+fn export_svg(_glyphs: &GlyphSet) {}
+
+// `--render=dir` draws the glyph sheet and SPECIMEN in each alignment into
+// PNG files in `dir` on the CPU, for machines without a GPU
+#[cfg(not(target_arch = "wasm32"))]
+fn render_png(glyphs: &GlyphSet) {
+    let dir = match arg("render") {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => return,
+    };
+    let mut sheet = Mesh::new();
+    for (_, glyph, corner) in glyphs.sheet(RENDER_WIDTH, RENDER_MARGIN) {
+        glyph.draw(&mut sheet, Col(Color::WHITE), Transform::translate(corner - glyph.bounds.pos), 0.0);
+    }
+    let mut meshes = vec![("glyphs.png".to_owned(), sheet)];
+    for (name, align) in ALIGNMENTS.iter() {
+        let mut text = Mesh::new();
+        specimen(*align).build(SPECIMEN, Col(Color::WHITE), glyphs)
+            .draw(&mut text, Col(Color::WHITE), Transform::IDENTITY, 0.0);
+        meshes.push((format!("text-{}.png", name), text));
+    }
+    for (name, mesh) in meshes.iter() {
+        let mut raster = Raster::fit(mesh, RENDER_MARGIN);
+        raster.clear(BG_COLOR);
+        raster.draw(mesh);
+        let path = dir.join(name);
+        if let Err(err) = std::fs::write(&path, raster.to_png()) {
+            println!("Could not write {}: {}", path.display(), err);
+        }
     }
 }
 
+#[cfg(target_arch = "wasm32")]
+fn render_png(_glyphs: &GlyphSet) {}
+
 #[cfg(not(target_arch = "wasm32"))]
 fn is_wasm() -> bool {
     return false;
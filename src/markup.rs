@@ -0,0 +1,219 @@
+use quicksilver::graphics::Color;
+
+// Inline markup for text laid out by TextLayout:
+//
+//   [color=#f80]..[/color]   tints what's inside, on top of the glyph colours
+//   [size=1.5]..[/size]      scales it relative to the layout's size
+//   {big}..{/big}            shorthand for [size=1.5], {small} for [size=0.6]
+//   [set=old_]..[/set]       prefers glyphs named with this prefix
+//   [icon=coin]              draws the named glyph inline
+//
+// Tags nest, and closing one also closes anything opened inside it.
+// `[[` and `{{` are a literal bracket, and anything that isn't a known tag
+// is drawn as typed.
+
+const BIG: f32 = 1.5;
+const SMALL: f32 = 0.6;
+
+#[derive(Clone, Debug)]
+pub struct Style {
+    pub color: Option<Color>,
+    // Relative to the layout's size
+    pub size: f32,
+    pub set: Option<String>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style{ color: None, size: 1.0, set: None }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    Char(char),
+    Icon(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub content: Content,
+    pub style: Style,
+}
+
+enum Tag {
+    Open(String, Style),
+    Close(String),
+    Icon(String),
+}
+
+fn hex_digit(c: char) -> Option<f32> {
+    c.to_digit(16).map(|d| d as f32)
+}
+
+// `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`
+pub fn parse_color(value: &str) -> Option<Color> {
+    if !value.starts_with('#') {
+        return None;
+    }
+    let digits = value[1..].chars().map(hex_digit).collect::<Option<Vec<f32>>>()?;
+    let channels: Vec<f32> = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d / 15.0).collect(),
+        6 | 8 => digits.chunks(2).map(|d| (d[0] * 16.0 + d[1]) / 255.0).collect(),
+        _ => return None,
+    };
+    Some(Color{
+        r: channels[0],
+        g: channels[1],
+        b: channels[2],
+        a: channels.get(3).cloned().unwrap_or(1.0),
+    })
+}
+
+fn parse_tag(tag: &str, brace: bool, style: &Style) -> Option<Tag> {
+    if tag.starts_with('/') {
+        return Some(Tag::Close(tag[1..].to_owned()));
+    }
+    if brace {
+        let size = match tag {
+            "big" => BIG,
+            "small" => SMALL,
+            _ => return None,
+        };
+        return Some(Tag::Open(tag.to_owned(), Style{ size: style.size * size, ..style.clone() }));
+    }
+
+    let eq = tag.find('=')?;
+    let (name, value) = (&tag[..eq], &tag[eq + 1..]);
+    let style = match name {
+        "color" => Style{ color: Some(parse_color(value)?), ..style.clone() },
+        "size" => Style{ size: style.size * value.parse::<f32>().ok()?, ..style.clone() },
+        "set" => Style{ set: Some(value.to_owned()), ..style.clone() },
+        "icon" => return Some(Tag::Icon(value.to_owned())),
+        _ => return None,
+    };
+    Some(Tag::Open(name.to_owned(), style))
+}
+
+pub fn parse(text: &str) -> Vec<Token> {
+    let base = Style::default();
+    let mut tokens = Vec::new();
+    // Open tags by name, with the style inside them
+    let mut open: Vec<(String, Style)> = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let style = open.last().map(|(_, style)| style).unwrap_or(&base).clone();
+        let close = match c {
+            '[' => Some(']'),
+            '{' => Some('}'),
+            _ => None,
+        };
+        if let Some(close) = close {
+            if rest[1..].starts_with(c) {
+                tokens.push(Token{ content: Content::Char(c), style: style });
+                rest = &rest[2..];
+                continue;
+            }
+            let tag = rest.find(close).and_then(|end|
+                parse_tag(&rest[1..end], c == '{', &style).map(|tag| (tag, end)));
+            if let Some((tag, end)) = tag {
+                match tag {
+                    Tag::Open(name, style) => open.push((name, style)),
+                    Tag::Close(name) => {
+                        // Closing a tag that isn't open does nothing
+                        if let Some(i) = open.iter().rposition(|(n, _)| *n == name) {
+                            open.truncate(i);
+                        }
+                    },
+                    Tag::Icon(name) => tokens.push(Token{ content: Content::Icon(name), style: style }),
+                }
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        tokens.push(Token{ content: Content::Char(c), style: style });
+        rest = &rest[c.len_utf8()..];
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color{ r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+
+    fn text(tokens: &[Token]) -> String {
+        tokens.iter().map(|token| match token.content {
+            Content::Char(c) => c.to_string(),
+            Content::Icon(ref name) => format!("<{}>", name),
+        }).collect()
+    }
+
+    fn sizes(tokens: &[Token]) -> Vec<f32> {
+        tokens.iter().map(|token| token.style.size).collect()
+    }
+
+    #[test]
+    fn nested_tags_stack_their_styles() {
+        let tokens = parse("[color=#f00]a[size=2]b{big}c{/big}d[/size]e[/color]f");
+        assert_eq!(text(&tokens), "abcdef");
+        assert_eq!(sizes(&tokens), vec![1.0, 2.0, 3.0, 2.0, 1.0, 1.0]);
+        for token in tokens[..5].iter() {
+            assert_eq!(token.style.color, Some(RED));
+        }
+        assert_eq!(tokens[5].style.color, None);
+    }
+
+    #[test]
+    fn closing_a_tag_closes_those_opened_inside_it() {
+        let tokens = parse("[color=#f00][set=old_]{small}a[/color]b");
+        assert_eq!(tokens[0].style.set, Some("old_".to_owned()));
+        assert_eq!(tokens[0].style.size, SMALL);
+        assert_eq!(tokens[1].style.color, None);
+        assert_eq!(tokens[1].style.set, None);
+        assert_eq!(tokens[1].style.size, 1.0);
+    }
+
+    #[test]
+    fn closing_a_tag_that_isnt_open_does_nothing() {
+        let tokens = parse("{big}a[/color]b{/big}c");
+        assert_eq!(text(&tokens), "abc");
+        assert_eq!(sizes(&tokens), vec![BIG, BIG, 1.0]);
+    }
+
+    #[test]
+    fn doubled_brackets_are_literal() {
+        assert_eq!(text(&parse("[[icon=coin]")), "[icon=coin]");
+        assert_eq!(text(&parse("{{big}}")), "{big}}");
+        assert_eq!(sizes(&parse("{{big}a")), vec![1.0; 6]);
+        assert_eq!(text(&parse("[[[icon=coin]")), "[<coin>");
+    }
+
+    #[test]
+    fn icons_take_the_style_around_them() {
+        let tokens = parse("a[icon=coin]{big}[icon=hero]{/big}");
+        assert_eq!(text(&tokens), "a<coin><hero>");
+        assert_eq!(tokens[2].content, Content::Icon("hero".to_owned()));
+        assert_eq!(tokens[2].style.size, BIG);
+    }
+
+    #[test]
+    fn unknown_or_broken_tags_are_drawn_as_typed() {
+        for typed in ["[nope]", "[color=red]x", "[size=big]", "{huge}", "[unclosed", "[=]"].iter() {
+            assert_eq!(text(&parse(typed)), *typed);
+        }
+    }
+
+    #[test]
+    fn colors_in_each_length() {
+        assert_eq!(parse_color("#f00"), Some(RED));
+        assert_eq!(parse_color("#ff0000"), Some(RED));
+        assert_eq!(parse_color("#ff000000"), Some(Color{ a: 0.0, ..RED }));
+        assert_eq!(parse_color("#f008"), Some(Color{ a: 8.0 / 15.0, ..RED }));
+        assert_eq!(parse_color("f00"), None);
+        assert_eq!(parse_color("#ff00"), Some(Color{ r: 1.0, g: 1.0, b: 0.0, a: 0.0 }));
+        assert_eq!(parse_color("#ff000"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+    }
+}
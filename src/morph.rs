@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, Mesh, ShapeRenderer},
+    lyon::{
+        geom::LineSegment,
+        math::Point,
+        path::PathEvent,
+        tessellation::{FillOptions, FillTessellator},
+    },
+};
+
+use crate::glyph::{Glyph, GlyphId, GlyphSet};
+
+// Points each curve is split into when flattening
+const CURVE_STEPS: usize = 8;
+// Points every contour is resampled to, so any two can be paired up
+const SAMPLES: usize = 64;
+// Morph factors are rounded to this many steps, so the cache stays small
+const FACTOR_STEPS: f32 = 64.0;
+
+fn vector(p: Point) -> Vector {
+    Vector{ x: p.x, y: p.y }
+}
+
+fn point(v: Vector) -> Point {
+    Point::new(v.x, v.y)
+}
+
+// Splits a path into its closed contours, with curves cut into lines
+pub fn flatten<I: IntoIterator<Item = PathEvent>>(events: I) -> Vec<Vec<Vector>> {
+    let mut contours = Vec::new();
+    let mut current: Vec<Vector> = Vec::new();
+    for event in events {
+        match event {
+            PathEvent::MoveTo(to) => {
+                if current.len() > 2 {
+                    contours.push(current);
+                }
+                current = vec![vector(to)];
+            },
+            PathEvent::Line(line) => current.push(vector(line.to)),
+            PathEvent::Quadratic(q) => {
+                let (from, ctrl, to) = (vector(q.from), vector(q.ctrl), vector(q.to));
+                for i in 1..=CURVE_STEPS {
+                    let t = i as f32 / CURVE_STEPS as f32;
+                    let u = 1.0 - t;
+                    current.push(from * (u * u) + ctrl * (2.0 * u * t) + to * (t * t));
+                }
+            },
+            PathEvent::Cubic(c) => {
+                let (from, ctrl1, ctrl2, to) = (vector(c.from), vector(c.ctrl1), vector(c.ctrl2), vector(c.to));
+                for i in 1..=CURVE_STEPS {
+                    let t = i as f32 / CURVE_STEPS as f32;
+                    let u = 1.0 - t;
+                    current.push(from * (u * u * u) + ctrl1 * (3.0 * u * u * t) + ctrl2 * (3.0 * u * t * t) + to * (t * t * t));
+                }
+            },
+            PathEvent::Close(_) => {
+                if current.len() > 2 {
+                    contours.push(current);
+                }
+                current = Vec::new();
+            },
+        }
+    }
+    if current.len() > 2 {
+        contours.push(current);
+    }
+    contours
+}
+
+// Twice the signed area, positive for clockwise contours on screen
+fn area(contour: &[Vector]) -> f32 {
+    (0..contour.len())
+        .map(|i| {
+            let (a, b) = (contour[i], contour[(i + 1) % contour.len()]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+fn centroid(contour: &[Vector]) -> Vector {
+    let sum = contour.iter().fold(Vector{ x: 0.0, y: 0.0 }, |sum, p| sum + *p);
+    sum / contour.len() as f32
+}
+
+// `SAMPLES` points evenly spaced along the closed contour
+fn resample(contour: &[Vector]) -> Vec<Vector> {
+    let len = contour.len();
+    let lengths: Vec<f32> = (0..len).map(|i| (contour[(i + 1) % len] - contour[i]).len()).collect();
+    let total: f32 = lengths.iter().sum();
+    if total <= 0.0 {
+        return vec![contour[0]; SAMPLES];
+    }
+    let mut points = Vec::with_capacity(SAMPLES);
+    let mut edge = 0;
+    let mut walked = 0.0;
+    for i in 0..SAMPLES {
+        let target = total * i as f32 / SAMPLES as f32;
+        while edge + 1 < len && walked + lengths[edge] < target {
+            walked += lengths[edge];
+            edge += 1;
+        }
+        let t = if lengths[edge] > 0.0 { (target - walked) / lengths[edge] } else { 0.0 };
+        let (a, b) = (contour[edge], contour[(edge + 1) % len]);
+        points.push(a + (b - a) * t.min(1.0));
+    }
+    points
+}
+
+// Turns `to` around and rotates where it starts so each of its points
+// travels as short a way as possible from the matching one in `from`
+fn align(from: &[Vector], mut to: Vec<Vector>) -> Vec<Vector> {
+    if (area(from) > 0.0) != (area(&to) > 0.0) {
+        to.reverse();
+    }
+    let cost = |offset: usize| -> f32 {
+        (0..SAMPLES).map(|i| (to[(i + offset) % SAMPLES] - from[i]).len2()).sum()
+    };
+    let best = (0..SAMPLES)
+        .min_by(|a, b| cost(*a).partial_cmp(&cost(*b)).unwrap_or(std::cmp::Ordering::Equal))
+        .unwrap_or(0);
+    to.rotate_left(best);
+    to
+}
+
+// A contour and the one it turns into. Contours without a partner grow
+// from or shrink to their centre.
+struct Pair {
+    from: Vec<Vector>,
+    to: Vec<Vector>,
+}
+
+fn pairs(from: &[Vec<Vector>], to: &[Vec<Vector>]) -> Vec<Pair> {
+    (0..from.len().max(to.len()))
+        .map(|i| match (from.get(i), to.get(i)) {
+            (Some(a), Some(b)) => {
+                let a = resample(a);
+                let b = align(&a, resample(b));
+                Pair{ from: a, to: b }
+            },
+            (Some(a), None) => Pair{ from: resample(a), to: vec![centroid(a); SAMPLES] },
+            (None, Some(b)) => Pair{ from: vec![centroid(b); SAMPLES], to: resample(b) },
+            (None, None) => unreachable!(),
+        })
+        .collect()
+}
+
+fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    Color{
+        r: a.r + (b.r - a.r) * t,
+        g: a.g + (b.g - a.g) * t,
+        b: a.b + (b.b - a.b) * t,
+        a: a.a + (b.a - a.a) * t,
+    }
+}
+
+fn lerp_rectangle(a: Rectangle, b: Rectangle, t: f32) -> Rectangle {
+    Rectangle::new(a.pos + (b.pos - a.pos) * t, a.size + (b.size - a.size) * t)
+}
+
+fn polygon_events(points: &[Vector]) -> Vec<PathEvent> {
+    let mut events = vec![PathEvent::MoveTo(point(points[0]))];
+    for i in 1..points.len() {
+        events.push(PathEvent::Line(LineSegment{ from: point(points[i - 1]), to: point(points[i]) }));
+    }
+    events.push(PathEvent::Close(LineSegment{ from: point(points[points.len() - 1]), to: point(points[0]) }));
+    events
+}
+
+// A path of a glyph cut into lines, with one colour for all of it
+struct Outline {
+    contours: Vec<Vec<Vector>>,
+    color: Color,
+}
+
+fn outlines(glyph: &Glyph) -> Vec<Outline> {
+    glyph.paths.iter()
+        .map(|path| {
+            let contours = path.contours();
+            let all: Vec<Vector> = contours.iter().flat_map(|c| c.iter().cloned()).collect();
+            let centre = if all.is_empty() { Vector{ x: 0.0, y: 0.0 } } else { centroid(&all) };
+            let color = match (&path.fill, &path.stroke) {
+                (Some(fill), _) => fill.color_at(centre),
+                (None, Some(stroke)) => stroke.paint.color_at(centre),
+                (None, None) => Color::WHITE,
+            };
+            Outline{ contours: contours, color: Color{ a: color.a * path.opacity, ..color } }
+        })
+        .collect()
+}
+
+// The outlines of two glyphs paired up, ready to be blended at any factor
+struct Blend {
+    // With the colours each outline goes between
+    outlines: Vec<(Vec<Pair>, Color, Color)>,
+    from_bounds: Rectangle,
+    to_bounds: Rectangle,
+}
+
+impl Blend {
+    fn new(from: &Glyph, to: &Glyph) -> Blend {
+        let (from_outlines, to_outlines) = (outlines(from), outlines(to));
+        let empty = Outline{ contours: Vec::new(), color: Color{ a: 0.0, ..Color::WHITE } };
+        let outlines = (0..from_outlines.len().max(to_outlines.len()))
+            .map(|i| {
+                let a = from_outlines.get(i).unwrap_or(&empty);
+                let b = to_outlines.get(i).unwrap_or(&empty);
+                // A shape fading in or out keeps its colour while it does
+                let from_color = if a.contours.is_empty() { Color{ a: 0.0, ..b.color } } else { a.color };
+                let to_color = if b.contours.is_empty() { Color{ a: 0.0, ..a.color } } else { b.color };
+                (pairs(&a.contours, &b.contours), from_color, to_color)
+            })
+            .collect();
+        Blend{ outlines: outlines, from_bounds: from.bounds, to_bounds: to.bounds }
+    }
+
+    fn build(&self, tessellator: &mut FillTessellator, name: String, t: f32) -> Glyph {
+        let mut mesh = Mesh::new();
+        for (pairs, from_color, to_color) in self.outlines.iter() {
+            let mut events = Vec::new();
+            for pair in pairs.iter() {
+                let points: Vec<Vector> = pair.from.iter().zip(pair.to.iter())
+                    .map(|(a, b)| *a + (*b - *a) * t)
+                    .collect();
+                events.extend(polygon_events(&points));
+            }
+            let mut shape_renderer = ShapeRenderer::new(&mut mesh, lerp_color(*from_color, *to_color, t));
+            // Contours that collapse to a point on the way don't tessellate
+            let _ = tessellator.tessellate_path(events, &FillOptions::tolerance(0.1), &mut shape_renderer);
+        }
+        Glyph::from_mesh(name, mesh, lerp_rectangle(self.from_bounds, self.to_bounds, t))
+    }
+}
+
+// Glyphs in between two others, built from their outlines on first use
+pub struct Morph {
+    tessellator: FillTessellator,
+    blends: HashMap<(GlyphId, GlyphId), Blend>,
+    cache: HashMap<(GlyphId, GlyphId, u32), Glyph>,
+}
+
+impl Morph {
+    pub fn new() -> Morph {
+        Morph{
+            tessellator: FillTessellator::new(),
+            blends: HashMap::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    // After the glyphs it was built from change
+    pub fn clear(&mut self) {
+        self.blends.clear();
+        self.cache.clear();
+    }
+
+    // `from` turned `t` of the way into `to`
+    pub fn get<'a>(&'a mut self, glyphs: &'a GlyphSet, from: GlyphId, to: GlyphId, t: f32) -> &'a Glyph {
+        let step = (t.max(0.0).min(1.0) * FACTOR_STEPS).round() as u32;
+        if step == 0 {
+            return glyphs.get_id(from);
+        }
+        if step == FACTOR_STEPS as u32 {
+            return glyphs.get_id(to);
+        }
+        let Morph{ ref mut tessellator, ref mut blends, ref mut cache } = *self;
+        cache.entry((from, to, step)).or_insert_with(|| {
+            let (a, b) = (glyphs.get_id(from), glyphs.get_id(to));
+            let blend = blends.entry((from, to))
+                .or_insert_with(|| Blend::new(a, b));
+            blend.build(tessellator, format!("{}>{}", a.name, b.name), step as f32 / FACTOR_STEPS)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::lyon::geom::QuadraticBezierSegment;
+
+    use super::*;
+
+    fn square(size: f32) -> Vec<Vector> {
+        vec![
+            Vector{ x: 0.0, y: 0.0 },
+            Vector{ x: size, y: 0.0 },
+            Vector{ x: size, y: size },
+            Vector{ x: 0.0, y: size },
+        ]
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn flatten_splits_contours_and_curves() {
+        let p = |x, y| Point::new(x, y);
+        let mut events = polygon_events(&square(10.0));
+        events.push(PathEvent::MoveTo(p(0.0, 0.0)));
+        events.push(PathEvent::Quadratic(QuadraticBezierSegment{ from: p(0.0, 0.0), ctrl: p(5.0, 10.0), to: p(10.0, 0.0) }));
+        // Too few points to enclose anything
+        events.push(PathEvent::MoveTo(p(0.0, 0.0)));
+        events.push(PathEvent::Line(LineSegment{ from: p(0.0, 0.0), to: p(1.0, 1.0) }));
+        let contours = flatten(events);
+        assert_eq!(contours.len(), 2);
+        assert_eq!(contours[0], square(10.0));
+        assert_eq!(contours[1].len(), CURVE_STEPS + 1);
+        assert_eq!(*contours[1].last().unwrap(), Vector{ x: 10.0, y: 0.0 });
+        assert!(close(contours[1][CURVE_STEPS / 2].y, 5.0));
+    }
+
+    #[test]
+    fn resample_spaces_points_evenly() {
+        let points = resample(&square(16.0));
+        assert_eq!(points.len(), SAMPLES);
+        let spacing = 16.0 * 4.0 / SAMPLES as f32;
+        for i in 0..SAMPLES {
+            assert!(close((points[(i + 1) % SAMPLES] - points[i]).len(), spacing), "{}", i);
+        }
+        assert!(close(area(&points), area(&square(16.0))));
+    }
+
+    #[test]
+    fn resample_collapses_zero_length_contours() {
+        let point = Vector{ x: 3.0, y: 4.0 };
+        assert_eq!(resample(&[point; 3]), vec![point; SAMPLES]);
+    }
+
+    #[test]
+    fn align_undoes_reversing_and_rotating() {
+        let from = resample(&square(16.0));
+        let mut to = from.clone();
+        to.reverse();
+        to.rotate_left(5);
+        assert_eq!(align(&from, to), from);
+    }
+
+    #[test]
+    fn square_morphed_into_itself_keeps_its_area() {
+        let mut reversed = square(16.0);
+        reversed.reverse();
+        for to in [square(16.0), reversed].iter() {
+            let pairs = pairs(&[square(16.0)], &[to.clone()]);
+            assert_eq!(pairs.len(), 1);
+            for step in 0..=FACTOR_STEPS as u32 {
+                let t = step as f32 / FACTOR_STEPS;
+                let points: Vec<Vector> = pairs[0].from.iter().zip(pairs[0].to.iter())
+                    .map(|(a, b)| *a + (*b - *a) * t)
+                    .collect();
+                assert!(close(area(&points), area(&square(16.0))), "{}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn unpaired_contours_grow_from_their_centre() {
+        let pairs = pairs(&[], &[square(16.0)]);
+        assert_eq!(pairs[0].from, vec![Vector{ x: 8.0, y: 8.0 }; SAMPLES]);
+        assert!(close(area(&pairs[0].to), area(&square(16.0))));
+    }
+
+    #[test]
+    fn ends_are_the_glyphs_themselves() {
+        let mut glyphs = GlyphSet::new().unwrap();
+        let from = glyphs.insert("from", Glyph::new("from".to_owned(), Mesh::new()));
+        let to = glyphs.insert("to", Glyph::new("to".to_owned(), Mesh::new()));
+        let mut morph = Morph::new();
+        assert_eq!(morph.get(&glyphs, from, to, 0.0).name, "from");
+        assert_eq!(morph.get(&glyphs, from, to, 1.0).name, "to");
+        // Rounded to the nearest step
+        assert_eq!(morph.get(&glyphs, from, to, 0.999).name, "to");
+        assert_eq!(morph.get(&glyphs, from, to, -1.0).name, "from");
+    }
+}
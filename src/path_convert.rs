@@ -1,13 +1,22 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
 use std::slice;
 
 use quicksilver::{
+    geom::Vector,
     lyon::math::Point,
     lyon::path::PathEvent,
-    lyon::geom::{LineSegment, CubicBezierSegment},
+    lyon::geom::{LineSegment, QuadraticBezierSegment, CubicBezierSegment},
 };
 
 use usvg;
 
+use crate::affine::Affine;
+
+// How far apart, in path units, the two control points a raised quadratic
+// implies may be before the cubic is kept as it is
+const QUAD_EPSILON: f32 = 1e-3;
+
 // This file is not mine
 // Taken from https://raw.githubusercontent.com/nical/lyon/master/examples/svg_render/src/path_convert.rs
 // This file is dual licensed under APACHE/MIT
@@ -16,57 +25,479 @@ use usvg;
 // This module implements some glue between usvg and lyon.
 // PathConvIter translate usvg's path data structure into an
 // iterator of lyon's PathEvent.
+//
+// Since extended to work on its own Segment type, so only UsvgSegments
+// below depends on the usvg version, with native quadratic and elliptical
+// arc segments, subpaths that don't start with a MoveTo and an optional
+// transform applied to every point.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    MoveTo(Point),
+    LineTo(Point),
+    QuadTo{ ctrl: Point, to: Point },
+    CurveTo{ ctrl1: Point, ctrl2: Point, to: Point },
+    // As in SVG's `A` command, `x_rotation` in degrees
+    ArcTo{ radii: Vector, x_rotation: f32, large_arc: bool, sweep: bool, to: Point },
+    Close,
+}
 
 fn point(x: &f64, y: &f64) -> Point {
     Point::new((*x) as f32, (*y) as f32)
 }
 
-pub struct PathConvIter<'a> {
+// usvg 0.4 reduces everything to move, line, cubic and close, storing
+// quadratics as cubics with their control points raised. Those are turned
+// back into quadratics, which flatten into fewer points.
+pub struct UsvgSegments<'a> {
     iter: slice::Iter<'a, usvg::PathSegment>,
     prev: Point,
     first: Point,
 }
 
-impl<'l> Iterator for PathConvIter<'l> {
-    type Item = PathEvent;
-    fn next(&mut self) -> Option<PathEvent> {
-        match self.iter.next() {
-            Some(usvg::PathSegment::MoveTo { x, y }) => {
-                self.prev = point(x, y);
-                self.first = self.prev;
-                Some(PathEvent::MoveTo(self.prev))
+impl<'a> UsvgSegments<'a> {
+    pub fn new(segments: &'a [usvg::PathSegment]) -> UsvgSegments<'a> {
+        UsvgSegments{ iter: segments.iter(), prev: Point::new(0.0, 0.0), first: Point::new(0.0, 0.0) }
+    }
+}
+
+// The control point of the quadratic a cubic was raised from, if it was
+fn lowered(from: Point, ctrl1: Point, ctrl2: Point, to: Point) -> Option<Point> {
+    let a = (from.x + (ctrl1.x - from.x) * 1.5, from.y + (ctrl1.y - from.y) * 1.5);
+    let b = (to.x + (ctrl2.x - to.x) * 1.5, to.y + (ctrl2.y - to.y) * 1.5);
+    if (a.0 - b.0).hypot(a.1 - b.1) <= QUAD_EPSILON {
+        Some(Point::new((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0))
+    } else {
+        None
+    }
+}
+
+impl<'a> Iterator for UsvgSegments<'a> {
+    type Item = Segment;
+    fn next(&mut self) -> Option<Segment> {
+        let segment = match self.iter.next()? {
+            usvg::PathSegment::MoveTo { x, y } => {
+                self.first = point(x, y);
+                Segment::MoveTo(self.first)
+            },
+            usvg::PathSegment::LineTo { x, y } => Segment::LineTo(point(x, y)),
+            usvg::PathSegment::CurveTo { x1, y1, x2, y2, x, y, } => {
+                let (ctrl1, ctrl2, to) = (point(x1, y1), point(x2, y2), point(x, y));
+                match lowered(self.prev, ctrl1, ctrl2, to) {
+                    Some(ctrl) => Segment::QuadTo{ ctrl: ctrl, to: to },
+                    None => Segment::CurveTo{ ctrl1: ctrl1, ctrl2: ctrl2, to: to },
+                }
+            },
+            usvg::PathSegment::ClosePath => {
+                self.prev = self.first;
+                return Some(Segment::Close);
+            },
+        };
+        match segment {
+            Segment::MoveTo(to) | Segment::LineTo(to) | Segment::QuadTo{ to, .. } | Segment::CurveTo{ to, .. } => self.prev = to,
+            _ => (),
+        }
+        Some(segment)
+    }
+}
+
+// Splits an SVG arc into cubic béziers of at most a quarter turn each,
+// following the endpoint to center conversion of the SVG spec (F.6.5)
+fn arc_to_cubics(from: Point, radii: Vector, x_rotation: f32, large_arc: bool, sweep: bool, to: Point) -> Vec<[Point; 3]> {
+    let (sin_phi, cos_phi) = x_rotation.to_radians().sin_cos();
+    let mut rx = radii.x.abs();
+    let mut ry = radii.y.abs();
+
+    let dx = (from.x - to.x) / 2.0;
+    let dy = (from.y - to.y) / 2.0;
+    let x1 = cos_phi * dx + sin_phi * dy;
+    let y1 = -sin_phi * dx + cos_phi * dy;
+
+    // Scale up radii too small to reach the end point
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+
+    let num = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let den = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let sign = if large_arc == sweep { -1.0 } else { 1.0 };
+    let coef = sign * (num / den).max(0.0).sqrt();
+    let cx1 = coef * rx * y1 / ry;
+    let cy1 = -coef * ry * x1 / rx;
+    let cx = cos_phi * cx1 - sin_phi * cy1 + (from.x + to.x) / 2.0;
+    let cy = sin_phi * cx1 + cos_phi * cy1 + (from.y + to.y) / 2.0;
+
+    let angle = |ux: f32, uy: f32, vx: f32, vy: f32| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let start = angle(1.0, 0.0, (x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut sweep_angle = angle((x1 - cx1) / rx, (y1 - cy1) / ry, (-x1 - cx1) / rx, (-y1 - cy1) / ry);
+    if !sweep && sweep_angle > 0.0 {
+        sweep_angle -= 2.0 * PI;
+    } else if sweep && sweep_angle < 0.0 {
+        sweep_angle += 2.0 * PI;
+    }
+
+    let on_ellipse = |theta: f32| {
+        let (sin, cos) = theta.sin_cos();
+        Point::new(
+            cx + rx * cos_phi * cos - ry * sin_phi * sin,
+            cy + rx * sin_phi * cos + ry * cos_phi * sin
+        )
+    };
+    let tangent = |theta: f32| {
+        let (sin, cos) = theta.sin_cos();
+        (-rx * cos_phi * sin - ry * sin_phi * cos, -rx * sin_phi * sin + ry * cos_phi * cos)
+    };
+
+    let count = (sweep_angle.abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+    let step = sweep_angle / count as f32;
+    let k = 4.0 / 3.0 * (step / 4.0).tan();
+    let mut cubics = Vec::with_capacity(count);
+    let mut from = from;
+    for i in 0..count {
+        let a = start + step * i as f32;
+        let b = a + step;
+        let (tax, tay) = tangent(a);
+        let (tbx, tby) = tangent(b);
+        let end = if i + 1 == count { to } else { on_ellipse(b) };
+        cubics.push([
+            Point::new(from.x + k * tax, from.y + k * tay),
+            Point::new(end.x - k * tbx, end.y - k * tby),
+            end,
+        ]);
+        from = end;
+    }
+    cubics
+}
+
+pub struct PathConvIter<I> {
+    iter: I,
+    transform: Affine,
+    // Both in the path's own (untransformed) space
+    prev: Point,
+    first: Point,
+    // Set until the current subpath has been started with a MoveTo
+    needs_move: bool,
+    pending: VecDeque<PathEvent>,
+}
+
+impl<I> PathConvIter<I> {
+    fn map(&self, p: Point) -> Point {
+        let v = self.transform * Vector{ x: p.x, y: p.y };
+        Point::new(v.x, v.y)
+    }
+
+    fn line(&self, from: Point, to: Point) -> LineSegment<f32> {
+        LineSegment { from: self.map(from), to: self.map(to) }
+    }
+
+    fn push(&mut self, segment: Segment) {
+        match segment {
+            Segment::MoveTo(to) => {
+                self.prev = to;
+                self.first = to;
+                self.needs_move = false;
+                let to = self.map(to);
+                self.pending.push_back(PathEvent::MoveTo(to));
+                return;
             }
-            Some(usvg::PathSegment::LineTo { x, y }) => {
-                let from = self.prev;
-                self.prev = point(x, y);
-                Some(PathEvent::Line(LineSegment { from, to: self.prev }))
+            Segment::Close => {
+                // Closing a subpath that was never started is a no-op
+                if !self.needs_move {
+                    let line = self.line(self.prev, self.first);
+                    self.pending.push_back(PathEvent::Close(line));
+                    self.prev = self.first;
+                    self.needs_move = true;
+                }
+                return;
             }
-            Some(usvg::PathSegment::CurveTo { x1, y1, x2, y2, x, y, }) => {
-                let from = self.prev;
-                self.prev = point(x, y);
-                Some(PathEvent::Cubic(CubicBezierSegment {
-                    from,
-                    ctrl1: point(x1, y1),
-                    ctrl2: point(x2, y2),
-                    to: self.prev,
-                }))
+            _ => {}
+        }
+
+        if self.needs_move {
+            // Drawing without a MoveTo starts a subpath at the current point
+            self.first = self.prev;
+            self.needs_move = false;
+            let from = self.map(self.prev);
+            self.pending.push_back(PathEvent::MoveTo(from));
+        }
+
+        let from = self.prev;
+        match segment {
+            Segment::LineTo(to) => {
+                let line = self.line(from, to);
+                self.pending.push_back(PathEvent::Line(line));
+                self.prev = to;
             }
-            Some(usvg::PathSegment::ClosePath) => {
-                self.prev = self.first;
-                Some(PathEvent::Close(LineSegment {
-                    from: self.prev,
-                    to: self.first,
-                }))
+            Segment::QuadTo { ctrl, to } => {
+                let quad = QuadraticBezierSegment {
+                    from: self.map(from),
+                    ctrl: self.map(ctrl),
+                    to: self.map(to),
+                };
+                self.pending.push_back(PathEvent::Quadratic(quad));
+                self.prev = to;
+            }
+            Segment::CurveTo { ctrl1, ctrl2, to } => {
+                self.push_cubic(from, ctrl1, ctrl2, to);
+                self.prev = to;
+            }
+            Segment::ArcTo { radii, x_rotation, large_arc, sweep, to } => {
+                if from == to {
+                    return;
+                }
+                if radii.x == 0.0 || radii.y == 0.0 {
+                    let line = self.line(from, to);
+                    self.pending.push_back(PathEvent::Line(line));
+                } else {
+                    let mut start = from;
+                    for [ctrl1, ctrl2, end] in arc_to_cubics(from, radii, x_rotation, large_arc, sweep, to) {
+                        self.push_cubic(start, ctrl1, ctrl2, end);
+                        start = end;
+                    }
+                }
+                self.prev = to;
+            }
+            Segment::MoveTo(_) | Segment::Close => unreachable!(),
+        }
+    }
+
+    fn push_cubic(&mut self, from: Point, ctrl1: Point, ctrl2: Point, to: Point) {
+        let cubic = CubicBezierSegment {
+            from: self.map(from),
+            ctrl1: self.map(ctrl1),
+            ctrl2: self.map(ctrl2),
+            to: self.map(to),
+        };
+        self.pending.push_back(PathEvent::Cubic(cubic));
+    }
+}
+
+impl<I: Iterator<Item = Segment>> Iterator for PathConvIter<I> {
+    type Item = PathEvent;
+    fn next(&mut self) -> Option<PathEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
             }
-            None => None,
+            let segment = self.iter.next()?;
+            self.push(segment);
         }
     }
 }
 
-pub fn convert_path<'a>(p: &'a usvg::Path) -> PathConvIter<'a> {
+pub fn convert_segments<I: IntoIterator<Item = Segment>>(segments: I, transform: Option<Affine>) -> PathConvIter<I::IntoIter> {
     PathConvIter {
-        iter: p.segments.iter(),
+        iter: segments.into_iter(),
+        transform: transform.unwrap_or(Affine::IDENTITY),
         first: Point::new(0.0, 0.0),
         prev: Point::new(0.0, 0.0),
+        needs_move: true,
+        pending: VecDeque::new(),
+    }
+}
+
+pub fn convert_path_with<'a>(p: &'a usvg::Path, transform: Option<Affine>) -> PathConvIter<UsvgSegments<'a>> {
+    convert_segments(UsvgSegments::new(&p.segments), transform)
+}
+
+pub fn convert_path<'a>(p: &'a usvg::Path) -> PathConvIter<UsvgSegments<'a>> {
+    convert_path_with(p, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f32, y: f32) -> Point {
+        Point::new(x, y)
+    }
+
+    fn close(a: Point, b: Point) -> bool {
+        (a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3
+    }
+
+    fn cubic_at(c: &CubicBezierSegment<f32>, t: f32) -> Point {
+        let s = 1.0 - t;
+        let (a, b, c2, d) = (s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t);
+        p(
+            a * c.from.x + b * c.ctrl1.x + c2 * c.ctrl2.x + d * c.to.x,
+            a * c.from.y + b * c.ctrl1.y + c2 * c.ctrl2.y + d * c.to.y
+        )
+    }
+
+    fn arc(radius: f32, large_arc: bool, sweep: bool, to: Point) -> Segment {
+        Segment::ArcTo{ radii: Vector{ x: radius, y: radius }, x_rotation: 0.0, large_arc: large_arc, sweep: sweep, to: to }
+    }
+
+    fn cubics(events: &[PathEvent]) -> Vec<CubicBezierSegment<f32>> {
+        events.iter().filter_map(|e| match e {
+            PathEvent::Cubic(c) => Some(*c),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn half_circle_arc_is_two_quarter_cubics_on_the_circle() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(10.0, 0.0)),
+            arc(10.0, false, true, p(-10.0, 0.0)),
+        ], None).collect();
+        let cubics = cubics(&events);
+        assert_eq!(cubics.len(), 2);
+        assert!(close(cubics[0].from, p(10.0, 0.0)));
+        assert!(close(cubics[1].to, p(-10.0, 0.0)));
+        // Sweeping towards positive angles goes through +y
+        assert!(close(cubics[0].to, p(0.0, 10.0)));
+        for c in cubics.iter() {
+            for i in 0..=8 {
+                let q = cubic_at(c, i as f32 / 8.0);
+                assert!((q.x.hypot(q.y) - 10.0).abs() < 0.01, "{:?} is off the circle", q);
+            }
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn arc_flags_pick_the_side_and_the_long_way_round() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(10.0, 0.0)),
+            arc(10.0, false, false, p(-10.0, 0.0)),
+        ], None).collect();
+        assert!(close(cubics(&events)[0].to, p(0.0, -10.0)));
+
+        // Three quarters of a circle around the origin
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(10.0, 0.0)),
+            arc(10.0, true, true, p(0.0, -10.0)),
+        ], None).collect();
+        let cubics = cubics(&events);
+        assert_eq!(cubics.len(), 3);
+        assert!(close(cubics[0].to, p(0.0, 10.0)));
+        assert!(close(cubics[2].to, p(0.0, -10.0)));
+    }
+
+    #[test]
+    fn radii_too_small_are_scaled_up_to_reach() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(10.0, 0.0)),
+            arc(1.0, false, true, p(-10.0, 0.0)),
+        ], None).collect();
+        let cubics = cubics(&events);
+        assert_eq!(cubics.len(), 2);
+        assert!(close(cubics[0].to, p(0.0, 10.0)));
+    }
+
+    #[test]
+    fn zero_radius_arc_is_a_line() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(0.0, 0.0)),
+            Segment::ArcTo{ radii: Vector{ x: 0.0, y: 5.0 }, x_rotation: 30.0, large_arc: true, sweep: true, to: p(10.0, 0.0) },
+        ], None).collect();
+        assert_eq!(events.len(), 2);
+        match events[1] {
+            PathEvent::Line(line) => {
+                assert!(close(line.from, p(0.0, 0.0)));
+                assert!(close(line.to, p(10.0, 0.0)));
+            },
+            ref other => panic!("expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn arc_to_the_current_point_draws_nothing() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(3.0, 4.0)),
+            arc(10.0, false, true, p(3.0, 4.0)),
+        ], None).collect();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn quadratics_stay_quadratics() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(0.0, 0.0)),
+            Segment::QuadTo{ ctrl: p(5.0, 10.0), to: p(10.0, 0.0) },
+        ], None).collect();
+        match events[1] {
+            PathEvent::Quadratic(q) => {
+                assert!(close(q.from, p(0.0, 0.0)));
+                assert!(close(q.ctrl, p(5.0, 10.0)));
+                assert!(close(q.to, p(10.0, 0.0)));
+            },
+            ref other => panic!("expected a quadratic, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn drawing_after_close_starts_a_subpath_at_its_start() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(1.0, 1.0)),
+            Segment::LineTo(p(10.0, 1.0)),
+            Segment::LineTo(p(10.0, 10.0)),
+            Segment::Close,
+            Segment::LineTo(p(5.0, 5.0)),
+            Segment::Close,
+        ], None).collect();
+        assert_eq!(events.len(), 7);
+        match (events[3], events[4], events[5], events[6]) {
+            (PathEvent::Close(closing), PathEvent::MoveTo(start), PathEvent::Line(line), PathEvent::Close(_)) => {
+                assert!(close(closing.to, p(1.0, 1.0)));
+                assert!(close(start, p(1.0, 1.0)));
+                assert!(close(line.from, p(1.0, 1.0)));
+                assert!(close(line.to, p(5.0, 5.0)));
+            },
+            other => panic!("unexpected events {:?}", other),
+        }
+    }
+
+    #[test]
+    fn closing_twice_closes_once() {
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(0.0, 0.0)),
+            Segment::LineTo(p(10.0, 0.0)),
+            Segment::Close,
+            Segment::Close,
+        ], None).collect();
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn transform_applies_to_every_point() {
+        let transform = Affine::translate(Vector{ x: 100.0, y: 0.0 }) * Affine::scale(Vector{ x: 2.0, y: 3.0 });
+        let events: Vec<PathEvent> = convert_segments(vec![
+            Segment::MoveTo(p(1.0, 1.0)),
+            Segment::QuadTo{ ctrl: p(2.0, 2.0), to: p(3.0, 1.0) },
+            Segment::CurveTo{ ctrl1: p(4.0, 0.0), ctrl2: p(5.0, 0.0), to: p(6.0, 1.0) },
+            Segment::Close,
+        ], Some(transform)).collect();
+        match (events[0], events[1], events[2], events[3]) {
+            (PathEvent::MoveTo(start), PathEvent::Quadratic(q), PathEvent::Cubic(c), PathEvent::Close(line)) => {
+                assert!(close(start, p(102.0, 3.0)));
+                assert!(close(q.ctrl, p(104.0, 6.0)));
+                assert!(close(q.to, p(106.0, 3.0)));
+                assert!(close(c.from, p(106.0, 3.0)));
+                assert!(close(c.ctrl1, p(108.0, 0.0)));
+                assert!(close(c.to, p(112.0, 3.0)));
+                assert!(close(line.from, p(112.0, 3.0)));
+                assert!(close(line.to, p(102.0, 3.0)));
+            },
+            other => panic!("unexpected events {:?}", other),
+        }
+    }
+
+    #[test]
+    fn raised_quadratics_from_usvg_are_lowered() {
+        let segments = vec![
+            usvg::PathSegment::MoveTo{ x: 0.0, y: 0.0 },
+            // Q 15 30 30 0, raised
+            usvg::PathSegment::CurveTo{ x1: 10.0, y1: 20.0, x2: 20.0, y2: 20.0, x: 30.0, y: 0.0 },
+            usvg::PathSegment::CurveTo{ x1: 30.0, y1: 10.0, x2: 0.0, y2: 10.0, x: 0.0, y: 0.0 },
+            usvg::PathSegment::ClosePath,
+        ];
+        let converted: Vec<Segment> = UsvgSegments::new(&segments).collect();
+        assert_eq!(converted[1], Segment::QuadTo{ ctrl: p(15.0, 30.0), to: p(30.0, 0.0) });
+        assert_eq!(converted[2], Segment::CurveTo{ ctrl1: p(30.0, 10.0), ctrl2: p(0.0, 10.0), to: p(0.0, 0.0) });
+        assert_eq!(converted[3], Segment::Close);
+    }
+}
@@ -0,0 +1,237 @@
+// Just enough of PNG and APNG to write RGBA images, without compressing
+// them
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+// Largest stored deflate block
+const BLOCK: usize = 65535;
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for n in 0..256 {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[n] = c;
+    }
+    table
+}
+
+fn crc(table: &[u32; 256], bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, b| table[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums stay under u32::MAX for this many bytes before the modulo
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// A zlib stream of stored blocks
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks: Vec<&[u8]> = data.chunks(BLOCK).collect();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.iter().enumerate() {
+        // Marks the final block
+        out.push(if i == last { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub struct Encoder {
+    bytes: Vec<u8>,
+    crc_table: [u32; 256],
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder{ bytes: SIGNATURE.to_vec(), crc_table: crc_table() }
+    }
+
+    pub fn chunk(&mut self, kind: &[u8; 4], data: &[u8]) {
+        self.bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(kind);
+        self.bytes.extend_from_slice(data);
+        let crc = crc(&self.crc_table, &self.bytes[start..]);
+        self.bytes.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    // 8 bit RGBA
+    pub fn header(&mut self, width: u32, height: u32) {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        self.chunk(b"IHDR", &data);
+    }
+
+    // The pixels compressed as PNG wants them, each row starting with its
+    // filter type
+    pub fn image_data(width: u32, rgba: &[u8]) -> Vec<u8> {
+        let stride = width as usize * 4;
+        let mut filtered = Vec::with_capacity(rgba.len() + rgba.len() / stride.max(1));
+        for row in rgba.chunks(stride.max(1)) {
+            filtered.push(0);
+            filtered.extend_from_slice(row);
+        }
+        zlib(&filtered)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.chunk(b"IEND", &[]);
+        self.bytes
+    }
+}
+
+// `rgba` is `width` * `height` pixels, row by row from the top
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder.header(width, height);
+    encoder.chunk(b"IDAT", &Encoder::image_data(width, rgba));
+    encoder.finish()
+}
+
+// An APNG looping forever, written a frame at a time so a long one can be
+// spread out. Viewers without APNG show the first frame.
+pub struct Animation {
+    encoder: Encoder,
+    width: u32,
+    height: u32,
+    delay: (u16, u16),
+    // Frame controls and frame data share one sequence
+    sequence: u32,
+}
+
+impl Animation {
+    // `frames` of them, each shown for `delay` seconds as a fraction
+    pub fn new(width: u32, height: u32, frames: u32, delay: (u16, u16)) -> Animation {
+        let mut encoder = Encoder::new();
+        encoder.header(width, height);
+        let mut control = Vec::with_capacity(8);
+        control.extend_from_slice(&frames.to_be_bytes());
+        control.extend_from_slice(&0u32.to_be_bytes());
+        encoder.chunk(b"acTL", &control);
+        Animation{ encoder: encoder, width: width, height: height, delay: delay, sequence: 0 }
+    }
+
+    // `rgba` is the next frame, as for `encode`
+    pub fn frame(&mut self, rgba: &[u8]) {
+        let first = self.sequence == 0;
+        let mut control = Vec::with_capacity(26);
+        control.extend_from_slice(&self.sequence.to_be_bytes());
+        control.extend_from_slice(&self.width.to_be_bytes());
+        control.extend_from_slice(&self.height.to_be_bytes());
+        control.extend_from_slice(&0u32.to_be_bytes());
+        control.extend_from_slice(&0u32.to_be_bytes());
+        control.extend_from_slice(&self.delay.0.to_be_bytes());
+        control.extend_from_slice(&self.delay.1.to_be_bytes());
+        // Frames cover the whole image and replace what was there
+        control.extend_from_slice(&[0, 0]);
+        self.encoder.chunk(b"fcTL", &control);
+        self.sequence += 1;
+
+        let data = Encoder::image_data(self.width, rgba);
+        if first {
+            self.encoder.chunk(b"IDAT", &data);
+        } else {
+            let mut frame = Vec::with_capacity(data.len() + 4);
+            frame.extend_from_slice(&self.sequence.to_be_bytes());
+            frame.extend_from_slice(&data);
+            self.encoder.chunk(b"fdAT", &frame);
+            self.sequence += 1;
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_matches_the_iend_chunk() {
+        assert_eq!(crc(&crc_table(), b"IEND"), 0xAE42_6082);
+        assert_eq!(&encode(0, 0, &[])[..8], &SIGNATURE);
+        assert!(encode(0, 0, &[]).ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn adler32_matches_known_sums() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn adler32_wraps_like_summing_byte_by_byte() {
+        let bytes = vec![0xff; 100_000];
+        let (mut a, mut b) = (1u32, 0u32);
+        for byte in bytes.iter() {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        assert_eq!(adler32(&bytes), (b << 16) | a);
+    }
+
+    #[test]
+    fn zlib_splits_into_stored_blocks() {
+        let data = vec![7; BLOCK + 1];
+        let out = zlib(&data);
+        assert_eq!(out.len(), 2 + (5 + BLOCK) + (5 + 1) + 4);
+        // Not the final block, then its length and the length's complement
+        assert_eq!(&out[2..7], &[0, 0xff, 0xff, 0, 0]);
+        assert_eq!(&out[7 + BLOCK..12 + BLOCK], &[1, 1, 0, 0xfe, 0xff]);
+        // An empty stream still has its one final block
+        assert_eq!(zlib(&[]), vec![0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn rows_start_with_their_filter() {
+        let data = Encoder::image_data(1, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&data[7..17], &[0, 1, 2, 3, 4, 0, 5, 6, 7, 8]);
+    }
+
+    // The kind of each chunk, in order
+    fn chunks(png: &[u8]) -> Vec<String> {
+        let mut kinds = Vec::new();
+        let mut at = SIGNATURE.len();
+        while at < png.len() {
+            let len = u32::from_be_bytes([png[at], png[at + 1], png[at + 2], png[at + 3]]) as usize;
+            kinds.push(String::from_utf8_lossy(&png[at + 4..at + 8]).into_owned());
+            at += 12 + len;
+        }
+        kinds
+    }
+
+    #[test]
+    fn animations_keep_the_first_frame_as_image_data() {
+        let mut animation = Animation::new(1, 1, 3, (1, 20));
+        for _ in 0..3 {
+            animation.frame(&[0, 0, 0, 255]);
+        }
+        assert_eq!(
+            chunks(&animation.finish()),
+            vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, GpuTriangle, Mesh, Vertex},
+};
+
+use crate::clock;
+
+// Frames shown in the graph
+const HISTORY: usize = 120;
+// Spans kept for the report, about a minute of frames. Older ones are
+// dropped so a long recording doesn't keep growing.
+const MAX_SPANS: usize = 50_000;
+// How much of each new timing goes into the averages
+const SMOOTHING: f64 = 0.1;
+// Frame time drawn as the full height of the graph, two frames at 60Hz
+const GRAPH_MS: f64 = 1000.0 / 30.0;
+const BUDGET_MS: f64 = 1000.0 / 60.0;
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 60.0;
+pub const GRAPH_SIZE: Vector = Vector{ x: HISTORY as f32 * BAR_WIDTH, y: GRAPH_HEIGHT };
+const FAST_COLOR: Color = Color{ r: 0.3, g: 0.9, b: 0.4, a: 0.8 };
+const SLOW_COLOR: Color = Color{ r: 1.0, g: 0.3, b: 0.2, a: 0.8 };
+const BUDGET_COLOR: Color = Color{ r: 1.0, g: 1.0, b: 1.0, a: 0.4 };
+
+// One timed scope
+struct Span {
+    name: &'static str,
+    frame: usize,
+    depth: usize,
+    start: f64,
+    duration: f64,
+}
+
+pub enum Format {
+    Csv,
+    // chrome://tracing and Perfetto's JSON format
+    ChromeTrace,
+}
+
+impl Format {
+    // Picked from the report's file extension
+    pub fn from_path(path: &str) -> Format {
+        if path.ends_with(".json") { Format::ChromeTrace } else { Format::Csv }
+    }
+}
+
+pub struct Profiler {
+    frame: usize,
+    frame_start: Option<f64>,
+    // Scopes begun and not yet ended
+    open: Vec<(&'static str, f64)>,
+    // Smoothed milliseconds per scope name, in the order first seen
+    averages: Vec<(&'static str, f64)>,
+    frame_times: VecDeque<f64>,
+    // The latest MAX_SPANS spans since recording started, for the report
+    recording: bool,
+    spans: VecDeque<Span>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler{
+            frame: 0,
+            frame_start: None,
+            open: Vec::new(),
+            averages: Vec::new(),
+            frame_times: VecDeque::with_capacity(HISTORY),
+            recording: false,
+            spans: VecDeque::new(),
+        }
+    }
+
+    // Allocates all the room the spans will need up front
+    pub fn record(&mut self) {
+        self.recording = true;
+        self.spans.reserve(MAX_SPANS);
+    }
+
+    // Called once at the start of every update, timing whole frames
+    pub fn frame(&mut self) {
+        let now = clock::now();
+        if let Some(start) = self.frame_start {
+            if self.frame_times.len() == HISTORY {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(now - start);
+        }
+        self.frame_start = Some(now);
+        self.frame += 1;
+    }
+
+    pub fn begin(&mut self, name: &'static str) {
+        self.open.push((name, clock::now()));
+    }
+
+    // Ends the innermost scope, which should be `name`
+    pub fn end(&mut self, name: &'static str) {
+        let end = clock::now();
+        let (begun, start) = match self.open.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        debug_assert_eq!(begun, name, "profiler scopes ended out of order");
+        let duration = end - start;
+
+        match self.averages.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, average)) => *average += (duration - *average) * SMOOTHING,
+            None => self.averages.push((name, duration)),
+        }
+        if self.recording {
+            if self.spans.len() == MAX_SPANS {
+                self.spans.pop_front();
+            }
+            self.spans.push_back(Span{
+                name: name,
+                frame: self.frame,
+                depth: self.open.len(),
+                start: start,
+                duration: duration,
+            });
+        }
+    }
+
+    // Average time of every scope, one per line, appended to `out`
+    pub fn write_summary(&self, out: &mut String) {
+        for (i, (name, ms)) in self.averages.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            // Writing to a String can't fail
+            let _ = write!(out, "{}: {:.2} ms", name, ms);
+        }
+    }
+
+    // A bar per recent frame, with a line at the 60Hz budget, drawn over
+    // whatever was in `mesh` to keep its buffers
+    pub fn graph(&self, mesh: &mut Mesh) {
+        mesh.clear();
+        let height = |ms: f64| (ms.min(GRAPH_MS) / GRAPH_MS) as f32 * GRAPH_HEIGHT;
+        for (i, ms) in self.frame_times.iter().enumerate() {
+            let color = if *ms > BUDGET_MS { SLOW_COLOR } else { FAST_COLOR };
+            let h = height(*ms);
+            push_rect(mesh, Rectangle::new(
+                Vector{ x: i as f32 * BAR_WIDTH, y: GRAPH_HEIGHT - h },
+                Vector{ x: BAR_WIDTH, y: h }
+            ), color);
+        }
+        let budget = GRAPH_HEIGHT - height(BUDGET_MS);
+        push_rect(mesh, Rectangle::new(
+            Vector{ x: 0.0, y: budget },
+            Vector{ x: GRAPH_SIZE.x, y: 1.0 }
+        ), BUDGET_COLOR);
+    }
+
+    pub fn report(&self, format: Format) -> String {
+        match format {
+            Format::Csv => {
+                let mut csv = String::from("frame,name,depth,start_ms,duration_ms\n");
+                for span in self.spans.iter() {
+                    csv.push_str(&format!("{},{},{},{:.4},{:.4}\n",
+                        span.frame, span.name, span.depth, span.start, span.duration));
+                }
+                csv
+            },
+            Format::ChromeTrace => {
+                // Times are in microseconds
+                let events: Vec<String> = self.spans.iter().map(|span| format!(
+                    "{{\"name\":\"{}\",\"cat\":\"frame\",\"ph\":\"X\",\"ts\":{:.1},\"dur\":{:.1},\"pid\":1,\"tid\":1,\"args\":{{\"frame\":{}}}}}",
+                    span.name, span.start * 1000.0, span.duration * 1000.0, span.frame
+                )).collect();
+                format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+            },
+        }
+    }
+}
+
+fn push_rect(mesh: &mut Mesh, rect: Rectangle, color: Color) {
+    let offset = mesh.vertices.len() as u32;
+    let corners = [
+        rect.pos,
+        rect.pos + Vector{ x: rect.size.x, y: 0.0 },
+        rect.pos + rect.size,
+        rect.pos + Vector{ x: 0.0, y: rect.size.y },
+    ];
+    mesh.vertices.extend(corners.iter()
+        .map(|pos| Vertex{ pos: *pos, tex_pos: None, col: color }));
+    for indices in [[0, 1, 2], [0, 2, 3]].iter() {
+        mesh.triangles.push(GpuTriangle{
+            z: 0.0,
+            indices: [offset + indices[0], offset + indices[1], offset + indices[2]],
+            image: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_stop_growing_at_the_cap() {
+        let mut profiler = Profiler::new();
+        profiler.record();
+        let capacity = profiler.spans.capacity();
+        for _ in 0..MAX_SPANS + 10 {
+            profiler.frame();
+            profiler.begin("update");
+            profiler.end("update");
+        }
+        assert_eq!(profiler.spans.len(), MAX_SPANS);
+        assert_eq!(profiler.spans.capacity(), capacity);
+        // The oldest went first
+        assert_eq!(profiler.spans.front().map(|span| span.frame), Some(11));
+    }
+
+    #[test]
+    fn nothing_is_kept_without_recording() {
+        let mut profiler = Profiler::new();
+        profiler.begin("update");
+        profiler.end("update");
+        assert!(profiler.spans.is_empty());
+        assert_eq!(profiler.report(Format::Csv).lines().count(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, Mesh},
+};
+
+use crate::affine::Affine;
+use crate::glyph_path::points_bounds;
+use crate::png;
+
+// Where each pixel is sampled, a rotated grid like 4x multisampling
+const SAMPLES: [Vector; 4] = [
+    Vector{ x: 0.375, y: 0.125 },
+    Vector{ x: 0.875, y: 0.375 },
+    Vector{ x: 0.125, y: 0.625 },
+    Vector{ x: 0.625, y: 0.875 },
+];
+
+// Twice the signed area of the triangle a, b, p
+fn edge(a: Vector, b: Vector, p: Vector) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// Edges on the top or left of a triangle own the samples exactly on them,
+// so triangles sharing an edge don't both draw it
+fn owns_edge(a: Vector, b: Vector) -> bool {
+    let d = b - a;
+    (d.y == 0.0 && d.x > 0.0) || d.y < 0.0
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+fn to_f32(c: u8) -> f32 {
+    c as f32 / 255.0
+}
+
+// `src` over `dest`, both straight alpha
+fn blend(dest: &mut [u8; 4], src: Color) {
+    let a = src.a.max(0.0).min(1.0);
+    let under = to_f32(dest[3]) * (1.0 - a);
+    let alpha = a + under;
+    if alpha <= 0.0 {
+        return;
+    }
+    let mix = |src: f32, dest: u8| to_u8((src * a + to_f32(dest) * under) / alpha);
+    *dest = [mix(src.r, dest[0]), mix(src.g, dest[1]), mix(src.b, dest[2]), to_u8(alpha)];
+}
+
+// Draws meshes without a GPU, as the window would with multisampling.
+// Textured triangles are drawn in their vertex colours.
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    // From mesh coordinates to pixels
+    to_pixels: Affine,
+    // SAMPLES.len() per pixel, straight alpha RGBA. Kept at 8 bits like a
+    // framebuffer, as a full screen of them adds up.
+    samples: Vec<[u8; 4]>,
+}
+
+impl Raster {
+    // `view` is the part of the mesh's coordinates that fills the image
+    pub fn new(width: u32, height: u32, view: Rectangle) -> Raster {
+        let scale = Vector{ x: width as f32 / view.size.x, y: height as f32 / view.size.y };
+        Raster{
+            width: width,
+            height: height,
+            to_pixels: Affine::scale(scale) * Affine::translate(-view.pos),
+            samples: vec![[0; 4]; (width * height) as usize * SAMPLES.len()],
+        }
+    }
+
+    // Just big enough for all of `mesh` and `margin` around it, a pixel per
+    // unit
+    pub fn fit(mesh: &Mesh, margin: f32) -> Raster {
+        let bounds = points_bounds(mesh.vertices.iter().map(|v| &v.pos))
+            .unwrap_or(Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }));
+        let (width, height) = ((bounds.size.x + margin * 2.0).ceil(), (bounds.size.y + margin * 2.0).ceil());
+        let view = Rectangle::new(bounds.pos - Vector{ x: margin, y: margin }, Vector{ x: width, y: height });
+        Raster::new(width as u32, height as u32, view)
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let color = [to_u8(color.r), to_u8(color.g), to_u8(color.b), to_u8(color.a)];
+        for sample in self.samples.iter_mut() {
+            *sample = color;
+        }
+    }
+
+    // The triangles from the lowest z up, like the window draws them
+    pub fn draw(&mut self, mesh: &Mesh) {
+        let mut order: Vec<usize> = (0..mesh.triangles.len()).collect();
+        order.sort_by(|a, b| mesh.triangles[*a].z.partial_cmp(&mesh.triangles[*b].z)
+            .unwrap_or(std::cmp::Ordering::Equal));
+        for i in order {
+            let triangle = &mesh.triangles[i];
+            let vertices = [
+                &mesh.vertices[triangle.indices[0] as usize],
+                &mesh.vertices[triangle.indices[1] as usize],
+                &mesh.vertices[triangle.indices[2] as usize],
+            ];
+            let points = [
+                self.to_pixels * vertices[0].pos,
+                self.to_pixels * vertices[1].pos,
+                self.to_pixels * vertices[2].pos,
+            ];
+            self.triangle(points, [vertices[0].col, vertices[1].col, vertices[2].col]);
+        }
+    }
+
+    fn triangle(&mut self, mut p: [Vector; 3], mut c: [Color; 3]) {
+        let mut area = edge(p[0], p[1], p[2]);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+        // Clockwise on screen from here on
+        if area < 0.0 {
+            p.swap(1, 2);
+            c.swap(1, 2);
+            area = -area;
+        }
+
+        let min_x = p.iter().map(|p| p.x).fold(std::f32::MAX, f32::min).floor().max(0.0) as u32;
+        let min_y = p.iter().map(|p| p.y).fold(std::f32::MAX, f32::min).floor().max(0.0) as u32;
+        let max_x = (p.iter().map(|p| p.x).fold(std::f32::MIN, f32::max).ceil().max(0.0) as u32).min(self.width);
+        let max_y = (p.iter().map(|p| p.y).fold(std::f32::MIN, f32::max).ceil().max(0.0) as u32).min(self.height);
+        let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+        let owns = [owns_edge(p[1], p[2]), owns_edge(p[2], p[0]), owns_edge(p[0], p[1])];
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let pixel = ((y * self.width + x) as usize) * SAMPLES.len();
+                for (s, offset) in SAMPLES.iter().enumerate() {
+                    let point = Vector{ x: x as f32, y: y as f32 } + *offset;
+                    let mut w = [0.0; 3];
+                    let mut inside = true;
+                    for i in 0..3 {
+                        w[i] = edge(edges[i].0, edges[i].1, point);
+                        if w[i] < 0.0 || (w[i] == 0.0 && !owns[i]) {
+                            inside = false;
+                            break;
+                        }
+                    }
+                    if !inside {
+                        continue;
+                    }
+                    let (w0, w1, w2) = (w[0] / area, w[1] / area, w[2] / area);
+                    let color = Color{
+                        r: c[0].r * w0 + c[1].r * w1 + c[2].r * w2,
+                        g: c[0].g * w0 + c[1].g * w1 + c[2].g * w2,
+                        b: c[0].b * w0 + c[1].b * w1 + c[2].b * w2,
+                        a: c[0].a * w0 + c[1].a * w1 + c[2].a * w2,
+                    };
+                    blend(&mut self.samples[pixel + s], color);
+                }
+            }
+        }
+    }
+
+    // The samples averaged into 8 bit RGBA pixels, row by row from the top
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for pixel in self.samples.chunks(SAMPLES.len()) {
+            // Weighted by alpha, so transparent samples don't darken the edges
+            let alpha: u32 = pixel.iter().map(|s| s[3] as u32).sum();
+            for i in 0..3 {
+                let sum: u32 = pixel.iter().map(|s| s[i] as u32 * s[3] as u32).sum();
+                rgba.push(if alpha > 0 { ((sum + alpha / 2) / alpha) as u8 } else { 0 });
+            }
+            rgba.push(((alpha + SAMPLES.len() as u32 / 2) / SAMPLES.len() as u32) as u8);
+        }
+        rgba
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.to_rgba())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::graphics::{GpuTriangle, Vertex};
+
+    use super::*;
+
+    // A rectangle as two triangles, in `color`
+    fn rect(mesh: &mut Mesh, min: Vector, max: Vector, color: Color) {
+        let offset = mesh.vertices.len() as u32;
+        for pos in [min, Vector{ x: max.x, y: min.y }, max, Vector{ x: min.x, y: max.y }].iter() {
+            mesh.vertices.push(Vertex{ pos: *pos, col: color, tex_pos: None });
+        }
+        for indices in [[0, 1, 2], [0, 2, 3]].iter() {
+            mesh.triangles.push(GpuTriangle{
+                z: 0.0,
+                indices: [offset + indices[0], offset + indices[1], offset + indices[2]],
+                image: None,
+            });
+        }
+    }
+
+    // The one pixel of a 1x1 raster with `mesh` drawn over transparent
+    fn pixel(mesh: &Mesh) -> Vec<u8> {
+        let mut raster = Raster::new(1, 1, Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }));
+        raster.clear(Color{ a: 0.0, ..Color::BLACK });
+        raster.draw(mesh);
+        raster.to_rgba()
+    }
+
+    #[test]
+    fn shared_edges_are_covered_once() {
+        // Right through the first sample
+        let split = SAMPLES[0].x;
+        let (left, right) = ((Vector{ x: 0.0, y: 0.0 }, Vector{ x: split, y: 1.0 }), (Vector{ x: split, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }));
+
+        let mut half = Mesh::new();
+        rect(&mut half, left.0, left.1, Color::WHITE);
+        let left_alpha = pixel(&half)[3] as u32;
+        let mut half = Mesh::new();
+        rect(&mut half, right.0, right.1, Color::WHITE);
+        let right_alpha = pixel(&half)[3] as u32;
+        assert!(left_alpha > 0 && right_alpha > 0);
+        assert_eq!(left_alpha + right_alpha, 255);
+
+        // Covered twice, the sample on the edge would be more opaque
+        let translucent = Color{ a: 0.5, ..Color::WHITE };
+        let mut both = Mesh::new();
+        rect(&mut both, left.0, left.1, translucent);
+        rect(&mut both, right.0, right.1, translucent);
+        assert_eq!(pixel(&both), vec![255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn winding_does_not_matter() {
+        let mut mesh = Mesh::new();
+        rect(&mut mesh, Vector{ x: 0.0, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }, Color::RED);
+        let clockwise = pixel(&mesh);
+        for triangle in mesh.triangles.iter_mut() {
+            triangle.indices.swap(1, 2);
+        }
+        assert_eq!(pixel(&mesh), clockwise);
+        assert_eq!(clockwise, vec![255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn higher_triangles_are_drawn_on_top() {
+        let mut mesh = Mesh::new();
+        rect(&mut mesh, Vector{ x: 0.0, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }, Color::BLUE);
+        rect(&mut mesh, Vector{ x: 0.0, y: 0.0 }, Vector{ x: 1.0, y: 1.0 }, Color::RED);
+        for triangle in mesh.triangles[..2].iter_mut() {
+            triangle.z = 1.0;
+        }
+        assert_eq!(pixel(&mesh), vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn fit_covers_the_mesh_and_margin() {
+        let mut mesh = Mesh::new();
+        rect(&mut mesh, Vector{ x: -5.0, y: 10.0 }, Vector{ x: 5.0, y: 12.5 }, Color::WHITE);
+        let raster = Raster::fit(&mesh, 2.0);
+        assert_eq!((raster.width, raster.height), (14, 7));
+    }
+}
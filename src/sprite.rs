@@ -1,69 +1,84 @@
+use quicksilver::{
+    geom::Vector,
+    graphics::Color,
+};
 
-use quicksilver::geom::{Rectangle, Shape, Vector};
+use crate::affine::Affine;
+use crate::glyph::GlyphId;
 
+// How an entity is drawn, at its position
 #[derive(Clone)]
 pub struct Sprite {
-    pub bounds: Rectangle,
-    pub speed: Vector,
-    pub visible: bool,
-    pub use_ttl: bool,
-    pub ttl: usize,
-    pub glyph: String,
+    pub glyph: GlyphId,
     pub scale: Vector,
+    // Degrees clockwise
+    pub rotation: f32,
+    // skewX and skewY in degrees
+    pub skew: Vector,
+    // The point of the glyph, relative to its origin, that sits at the
+    // entity's position and that it rotates and scales around
+    pub pivot: Vector,
+    // Drawn `morph` of the way to turning into this glyph
+    pub morph_to: Option<GlyphId>,
+    pub morph: f32,
     pub z: f32,
+    pub visible: bool,
+    pub tint: Color,
 }
 
 impl Sprite {
-    pub fn new(glyph: &str, pos: Vector) -> Sprite {
-        // TODO: Calculate from the glyph's mesh
-        let size = Vector{ x: 100.0, y: 100.0 };
+    pub fn new(glyph: GlyphId) -> Sprite {
         Sprite{
-            bounds: Rectangle {
-                pos: pos - size/2, 
-                size: size,
-            },
-            speed: Vector{ x: 0.0, y: 0.0 },
-            visible: true,
-            use_ttl: false,
-            ttl: 1,
-            glyph: glyph.to_owned(),
+            glyph: glyph,
             scale: Vector{x: 1.0, y: 1.0},
+            rotation: 0.0,
+            skew: Vector{x: 0.0, y: 0.0},
+            pivot: Vector{x: 0.0, y: 0.0},
+            morph_to: None,
+            morph: 0.0,
             z: 0.0,
+            visible: true,
+            tint: Color::WHITE,
         }
     }
 
-    pub fn move_by(&mut self, pos: Vector) {
-        self.bounds.pos += pos;
-    }
-
-    pub fn move_to(&mut self, pos: Vector) {
-        self.bounds.pos = pos - self.bounds.size/2;
-    }
-
-}
-
-impl Shape for Sprite {
-    //`overlaps`, `center`, `bounding_box`, `translate`
-
-    fn center(&self) -> Vector {
-        self.bounds.center()
-    }
-
-    fn contains(&self, point: impl Into<Vector>) -> bool {
-        self.bounds.contains(point)
+    // The tint to draw with, faded out over the last `fade` frames of the
+    // lifetime
+    pub fn color(&self, lifetime: Option<&f32>, fade: Option<&f32>) -> Color {
+        match (lifetime, fade) {
+            (Some(ttl), Some(fade)) if *ttl < *fade => {
+                Color{ a: self.tint.a * ttl.max(0.0) / *fade, ..self.tint }
+            },
+            _ => self.tint,
+        }
     }
 
-    fn overlaps(&self, shape: &impl Shape) -> bool {
-        self.bounds.overlaps(shape)
+    // From glyph space to the parent's, or the screen without one, with
+    // the sprite at `pos`
+    pub fn transform(&self, pos: Vector) -> Affine {
+        Affine::translate(pos)
+            * Affine::rotate(self.rotation)
+            * Affine::skew(self.skew)
+            * Affine::scale(self.scale)
+            * Affine::translate(-self.pivot)
     }
 
-    fn bounding_box(&self) -> Rectangle {
-        self.bounds
+    // Takes on the rotation, skew and scale of `transform`, which is then
+    // what `transform` returns at the position it sends the pivot to
+    pub fn set_transform(&mut self, transform: Affine) {
+        if let Some((rotation, skew, scale)) = transform.decompose() {
+            self.rotation = rotation;
+            self.skew = skew;
+            self.scale = scale;
+        }
     }
 
-    fn translate(&self, v: impl Into<Vector>) -> Self {
-        let mut sprite = self.clone();
-        sprite.bounds.pos += v.into();
-        sprite
+    // Turns the sprite so the direction its glyph faces unrotated,
+    // `forward` degrees, points along `velocity`. Standing still keeps
+    // the last heading.
+    pub fn face(&mut self, velocity: Vector, forward: f32) {
+        if velocity.x != 0.0 || velocity.y != 0.0 {
+            self.rotation = velocity.angle() - forward;
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Color, Mesh},
+    lyon::path::{Path, PathEvent},
+};
+
+use usvg;
+
+use crate::affine::Affine;
+use crate::glyph::{self, Glyph, GlyphSet};
+use crate::gradient::{Gradient, Shape};
+use crate::glyph_path::{points_bounds, GlyphPath, Paint};
+use crate::text::TextLayout;
+
+// Of the glyph sheet
+const SHEET_WIDTH: f32 = 1200.0;
+// Around each glyph on the sheet, and around the whole document
+const MARGIN: f32 = 20.0;
+const LABEL_SIZE: f32 = 10.0;
+// Drawn over each triangle's edges so the tessellation shows
+const EDGE_COLOR: &str = "#000000";
+const EDGE_OPACITY: f32 = 0.5;
+const EDGE_WIDTH: f32 = 0.3;
+
+// `#rrggbb`, the alpha goes in a separate opacity attribute
+fn rgb(color: Color) -> String {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(color.r), channel(color.g), channel(color.b))
+}
+
+fn matrix(t: Affine) -> String {
+    format!("matrix({} {} {} {} {} {})", t.a, t.b, t.c, t.d, t.e, t.f)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn points(points: &[Vector]) -> String {
+    points.iter()
+        .map(|p| format!("{},{}", p.x, p.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn path_data(path: &Path) -> String {
+    let mut d = String::new();
+    for event in path.iter() {
+        let segment = match event {
+            PathEvent::MoveTo(to) => format!("M{} {} ", to.x, to.y),
+            PathEvent::Line(line) => format!("L{} {} ", line.to.x, line.to.y),
+            PathEvent::Quadratic(q) => format!("Q{} {} {} {} ", q.ctrl.x, q.ctrl.y, q.to.x, q.to.y),
+            PathEvent::Cubic(c) => format!("C{} {} {} {} {} {} ", c.ctrl1.x, c.ctrl1.y, c.ctrl2.x, c.ctrl2.y, c.to.x, c.to.y),
+            PathEvent::Close(_) => "Z ".to_owned(),
+        };
+        d.push_str(&segment);
+    }
+    d.trim_end().to_owned()
+}
+
+// A <linearGradient> or <radialGradient> with `id`, in the user space of
+// whatever it's used on, each stop tinted by `tint`
+fn gradient(gradient: &Gradient, id: &str, tint: Color, out: &mut String) {
+    let to_user = gradient.to_gradient().inverse().unwrap_or(Affine::IDENTITY);
+    let spread = match gradient.spread() {
+        usvg::SpreadMethod::Pad => "pad",
+        usvg::SpreadMethod::Reflect => "reflect",
+        usvg::SpreadMethod::Repeat => "repeat",
+    };
+    let (tag, shape) = match *gradient.shape() {
+        Shape::Linear{ from, to } => ("linearGradient", format!(
+            "x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"", from.x, from.y, to.x, to.y)),
+        Shape::Radial{ center, focus, radius } => ("radialGradient", format!(
+            "cx=\"{}\" cy=\"{}\" fx=\"{}\" fy=\"{}\" r=\"{}\"", center.x, center.y, focus.x, focus.y, radius)),
+    };
+    out.push_str(&format!(
+        "<{} id=\"{}\" gradientUnits=\"userSpaceOnUse\" gradientTransform=\"{}\" spreadMethod=\"{}\" {}>\n",
+        tag, id, matrix(to_user), spread, shape));
+    for (offset, color) in gradient.stops().iter() {
+        let color = glyph::tint(*color, tint);
+        out.push_str(&format!(
+            "<stop offset=\"{}\" stop-color=\"{}\" stop-opacity=\"{}\"/>\n",
+            offset, rgb(color), color.a));
+    }
+    out.push_str(&format!("</{}>\n", tag));
+}
+
+// An SVG document built up from glyphs and meshes
+pub struct Document {
+    defs: String,
+    body: String,
+    // Numbers the gradients and clip paths
+    next_id: usize,
+    // Glyph group ids, which repeat in text
+    names: HashSet<String>,
+    // Of everything drawn so far
+    bounds: Option<Rectangle>,
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document{
+            defs: String::new(),
+            body: String::new(),
+            next_id: 0,
+            names: HashSet::new(),
+            bounds: None,
+        }
+    }
+
+    fn id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{}", prefix, self.next_id)
+    }
+
+    fn include(&mut self, rect: Rectangle, transform: Affine) {
+        let corners = [
+            rect.pos,
+            rect.pos + Vector{ x: rect.size.x, y: 0.0 },
+            rect.pos + rect.size,
+            rect.pos + Vector{ x: 0.0, y: rect.size.y },
+        ];
+        let (mut min, mut max) = match self.bounds {
+            Some(b) => (b.pos, b.pos + b.size),
+            None => (transform * corners[0], transform * corners[0]),
+        };
+        for corner in corners.iter() {
+            let p = transform * *corner;
+            min = Vector{ x: min.x.min(p.x), y: min.y.min(p.y) };
+            max = Vector{ x: max.x.max(p.x), y: max.y.max(p.y) };
+        }
+        self.bounds = Some(Rectangle{ pos: min, size: max - min });
+    }
+
+    // The fill or stroke attribute for `paint`, and its opacity
+    fn paint(&mut self, paint: &Paint, tint: Color) -> (String, f32) {
+        match paint {
+            Paint::Color(color) => {
+                let color = glyph::tint(*color, tint);
+                (rgb(color), color.a)
+            },
+            Paint::Gradient(paint) => {
+                let id = self.id("gradient");
+                gradient(paint, &id, tint, &mut self.defs);
+                (format!("url(#{})", id), 1.0)
+            },
+        }
+    }
+
+    fn path(&mut self, path: &GlyphPath, tint: Color) {
+        // Each clip is convex, and the path is inside all of them
+        for clip in path.clips.iter() {
+            let id = self.id("clip");
+            self.defs.push_str(&format!("<clipPath id=\"{}\"><polygon points=\"{}\"/></clipPath>\n", id, points(clip)));
+            self.body.push_str(&format!("<g clip-path=\"url(#{})\">\n", id));
+        }
+        let fill = match path.fill {
+            Some(ref paint) => {
+                let (fill, opacity) = self.paint(paint, tint);
+                format!("fill=\"{}\" fill-opacity=\"{}\"", fill, opacity)
+            },
+            None => "fill=\"none\"".to_owned(),
+        };
+        let stroke = match path.stroke {
+            Some(ref stroke) => {
+                let (paint, opacity) = self.paint(&stroke.paint, tint);
+                format!(" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"", paint, opacity, stroke.width)
+            },
+            None => String::new(),
+        };
+        self.body.push_str(&format!(
+            "<path d=\"{}\" {}{} opacity=\"{}\"/>\n",
+            path_data(&path.path), fill, stroke, path.opacity));
+        for _ in path.clips.iter() {
+            self.body.push_str("</g>\n");
+        }
+    }
+
+    // Every triangle as a polygon in the average of its vertex colours,
+    // with its edges drawn over it when `edges` is set
+    fn triangles(&mut self, mesh: &Mesh, tint: Color, edges: bool) {
+        for triangle in mesh.triangles.iter() {
+            let vertices: Vec<_> = triangle.indices.iter().map(|i| &mesh.vertices[*i as usize]).collect();
+            let corners: Vec<Vector> = vertices.iter().map(|v| v.pos).collect();
+            let sum = vertices.iter().fold((0.0, 0.0, 0.0, 0.0), |(r, g, b, a), v|
+                (r + v.col.r, g + v.col.g, b + v.col.b, a + v.col.a));
+            let color = glyph::tint(Color{ r: sum.0 / 3.0, g: sum.1 / 3.0, b: sum.2 / 3.0, a: sum.3 / 3.0 }, tint);
+            let edge = if edges {
+                format!(" stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\"", EDGE_COLOR, EDGE_OPACITY, EDGE_WIDTH)
+            } else {
+                String::new()
+            };
+            self.body.push_str(&format!(
+                "<polygon points=\"{}\" fill=\"{}\" fill-opacity=\"{}\"{}/>\n",
+                points(&corners), rgb(color), color.a, edge));
+        }
+    }
+
+    // For meshes that aren't glyphs
+    pub fn mesh(&mut self, mesh: &Mesh, transform: Affine, tint: Color, edges: bool) {
+        self.body.push_str(&format!("<g transform=\"{}\">\n", matrix(transform)));
+        self.triangles(mesh, tint, edges);
+        self.body.push_str("</g>\n");
+        if let Some(bounds) = points_bounds(mesh.vertices.iter().map(|v| &v.pos)) {
+            self.include(bounds, transform);
+        }
+    }
+
+    // A group named after the glyph with its paths, or its triangles if it
+    // has none. With `triangles` the mesh it's drawn with goes on top.
+    pub fn glyph(&mut self, glyph: &Glyph, transform: Affine, tint: Color, triangles: bool) {
+        let mut name = glyph.name.clone();
+        if self.names.contains(&name) {
+            name = self.id(&format!("{}_", glyph.name));
+        }
+        self.names.insert(name.clone());
+        self.body.push_str(&format!("<g id=\"{}\" transform=\"{}\">\n", escape(&name), matrix(transform)));
+        let mesh = glyph.lod(transform.max_scale());
+        if glyph.paths.is_empty() {
+            self.triangles(mesh, tint, triangles);
+        } else {
+            for path in glyph.paths.iter() {
+                self.path(path, tint);
+            }
+            if triangles {
+                self.triangles(mesh, tint, true);
+            }
+        }
+        self.body.push_str("</g>\n");
+        self.include(glyph.bounds, transform);
+    }
+
+    pub fn label(&mut self, text: &str, pos: Vector) {
+        self.body.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"{}\" fill=\"#ffffff\">{}</text>\n",
+            pos.x, pos.y, LABEL_SIZE, escape(text)));
+    }
+
+    pub fn finish(self, background: Color) -> String {
+        let bounds = self.bounds.unwrap_or(Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: 0.0, y: 0.0 }));
+        let pos = bounds.pos - Vector{ x: MARGIN, y: MARGIN };
+        let size = bounds.size + Vector{ x: MARGIN, y: MARGIN } * 2.0;
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"{x} {y} {w} {h}\">\n\
+             <defs>\n{defs}</defs>\n\
+             <rect x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" fill=\"{bg}\"/>\n\
+             {body}</svg>\n",
+            x = pos.x, y = pos.y, w = size.x, h = size.y,
+            defs = self.defs, bg = rgb(background), body = self.body)
+    }
+}
+
+// Every glyph of the set in a grid, by name, each labelled
+pub fn glyph_sheet(glyphs: &GlyphSet, triangles: bool, background: Color) -> String {
+    let mut document = Document::new();
+    for (name, glyph, corner) in glyphs.sheet(SHEET_WIDTH, MARGIN + LABEL_SIZE) {
+        document.glyph(glyph, Affine::translate(corner - glyph.bounds.pos), Color::WHITE, triangles);
+        document.label(name, corner + Vector{ x: 0.0, y: glyph.bounds.size.y + LABEL_SIZE });
+    }
+    document.finish(background)
+}
+
+// `text` laid out as the game would draw it, keeping the glyphs' paths
+pub fn text(layout: &TextLayout, text: &str, glyphs: &GlyphSet, triangles: bool, background: Color) -> String {
+    let mut document = Document::new();
+    for placement in layout.place(text, glyphs) {
+        let transform = Affine::translate(placement.pos) *
+            Affine::scale(Vector{ x: placement.scale, y: placement.scale });
+        document.glyph(placement.glyph, transform, placement.tint.unwrap_or(Color::WHITE), triangles);
+    }
+    document.finish(background)
+}
+
+// A frame as the game drew it, triangle by triangle, framed by `view`
+pub fn frame(mesh: &Mesh, view: Rectangle, background: Color) -> String {
+    let mut document = Document::new();
+    document.include(view, Affine::IDENTITY);
+    document.mesh(mesh, Affine::IDENTITY, Color::WHITE, false);
+    document.finish(background)
+}
+
+#[cfg(test)]
+mod tests {
+    use quicksilver::graphics::{GpuTriangle, Vertex};
+
+    use super::*;
+
+    #[test]
+    fn frames_keep_their_view_and_triangles() {
+        let mut mesh = Mesh::new();
+        for pos in [Vector{ x: 0.0, y: 0.0 }, Vector{ x: 10.0, y: 0.0 }, Vector{ x: 0.0, y: 10.0 }].iter() {
+            mesh.vertices.push(Vertex{ pos: *pos, col: Color::RED, tex_pos: None });
+        }
+        mesh.triangles.push(GpuTriangle{ z: 0.0, indices: [0, 1, 2], image: None });
+        let view = Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: 100.0, y: 50.0 });
+        let svg = frame(&mesh, view, Color::BLACK);
+        assert!(svg.contains("viewBox=\"-20 -20 140 90\""), "{}", svg);
+        assert!(svg.contains("<polygon points=\"0,0 10,0 0,10\" fill=\"#ff0000\" fill-opacity=\"1\"/>"), "{}", svg);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+// usvg throws away everything it doesn't need to render, including
+// `class`, `inkscape:label` and `data-*` attributes. This is a small scan
+// over the raw document collecting the attributes of every element with
+// an id, so the glyph loader can read its metadata from them.
+
+pub type Attributes = HashMap<String, String>;
+
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok()
+                .and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse::<u32>().ok()
+                .and_then(std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn parse_attributes(tag: &str) -> Attributes {
+    let mut attributes = HashMap::new();
+    let mut rest = tag;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        attributes.insert(name.to_owned(), decode_entities(&value[1..end]));
+        rest = &value[end + 1..];
+    }
+    attributes
+}
+
+// Maps element ids to all of their attributes
+pub fn parse(bytes: &[u8]) -> HashMap<String, Attributes> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut elements = HashMap::new();
+    let mut rest: &str = &text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let skip_to = if rest.starts_with("!--") {
+            "-->"
+        } else if rest.starts_with("![CDATA[") {
+            "]]>"
+        } else if rest.starts_with('?') || rest.starts_with('!') || rest.starts_with('/') {
+            ">"
+        } else {
+            ""
+        };
+        if !skip_to.is_empty() {
+            rest = match rest.find(skip_to) {
+                Some(end) => &rest[end + skip_to.len()..],
+                None => "",
+            };
+            continue;
+        }
+
+        // Attribute values may legally contain '>', so find the end of the
+        // tag by skipping over quoted sections
+        let mut end = rest.len();
+        let mut quote = None;
+        for (i, c) in rest.char_indices() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {},
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if c == '>' => { end = i; break; },
+                None => {},
+            }
+        }
+        let tag = rest[..end].trim_end_matches('/');
+        let name_end = tag.find(char::is_whitespace).unwrap_or_else(|| tag.len());
+        let attributes = parse_attributes(&tag[name_end..]);
+        if let Some(id) = attributes.get("id").cloned() {
+            elements.insert(id, attributes);
+        }
+        rest = &rest[end..];
+    }
+    elements
+}
+
+// Whether `attributes` has `class` among its (whitespace separated) classes
+pub fn has_class(attributes: &Attributes, class: &str) -> bool {
+    match attributes.get("class") {
+        Some(classes) => classes.split_whitespace().any(|c| c == class),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_angle_brackets_stay_in_the_tag() {
+        let svg = br#"<svg><g id="glyph_a" data-note="a > b" data-next='<c>' class="glyph"><path id="p"/></g></svg>"#;
+        let elements = parse(svg);
+        let glyph = &elements["glyph_a"];
+        assert_eq!(glyph["data-note"], "a > b");
+        assert_eq!(glyph["data-next"], "<c>");
+        assert_eq!(glyph["class"], "glyph");
+        assert!(elements.contains_key("p"));
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        let svg = br#"<g id="e" data-char="&lt;&amp;&gt;&quot;&apos;" data-hex="&#x263A;&#65;" data-bad="&nope; &amp"/>"#;
+        let attributes = &parse(svg)["e"];
+        assert_eq!(attributes["data-char"], "<&>\"'");
+        assert_eq!(attributes["data-hex"], "\u{263A}A");
+        assert_eq!(attributes["data-bad"], "&nope; &amp");
+    }
+
+    #[test]
+    fn comments_cdata_and_closing_tags_are_skipped() {
+        let svg = br#"<?xml version="1.0"?><!-- <g id="commented"/> --><style><![CDATA[ <g id="cdata"/> ]]></style><g id="real"></g>"#;
+        let elements = parse(svg);
+        assert_eq!(elements.len(), 1);
+        assert!(elements.contains_key("real"));
+    }
+
+    #[test]
+    fn classes_are_whitespace_separated() {
+        let attributes = &parse(br#"<g id="g" class="  glyph  hidden"/>"#)["g"];
+        assert!(has_class(attributes, "glyph"));
+        assert!(has_class(attributes, "hidden"));
+        assert!(!has_class(attributes, "glyp"));
+    }
+}
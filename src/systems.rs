@@ -0,0 +1,562 @@
+use std::mem;
+
+use rand::{Rng, rngs::StdRng};
+use quicksilver::{
+    geom::{Rectangle, Shape, Transform, Vector},
+    graphics::{Background::Col, Color},
+    lifecycle::Window,
+};
+
+use crate::affine::Affine;
+use crate::anim::{Animation, ClipId, Mode};
+use crate::collide;
+use crate::components::{Cursor, Flash, Hero, Parent, WorldTransform};
+use crate::glyph::{ColorMode, GlyphId, GlyphSet};
+use crate::hud::HUD_Z;
+use crate::input::Input;
+use crate::morph::Morph;
+use crate::sprite::Sprite;
+use crate::tween::{self, Ease, Property, Tween};
+use crate::world::{Entity, Storage, World};
+
+const ACCELERATION: f32 = 2.5;
+// Velocities slower than this stop entirely under friction
+const REST_SPEED: f32 = 0.1;
+const SHOT_COOLDOWN: f32 = 6.0;
+const SHOT_SPEED: f32 = 20.0;
+// Shots start this far out from the hero's position
+const SHOT_OFFSET: f32 = 50.0;
+const SHOT_TTL: f32 = 60.0;
+// Each shot costs the hero this much of their size, regrown while idle
+const SHOT_COST: f32 = 0.99;
+// Regrowing takes as long as growing by this much a frame would
+const REGROWTH: f32 = 1.001;
+// Frames without shooting before the hero starts to regrow
+const REGROW_DELAY: f32 = 30.0;
+// The hero has fully melted into a coin once it has lost this much size
+const MELTED: f32 = 0.25;
+// The hero flashes this colour for a few frames after each shot
+const FLASH_COLOR: Color = Color{ r: 1.0, g: 0.9, b: 0.6, a: 1.0};
+const FLASH_FRAMES: f32 = 4.0;
+const CURSOR_IDLE: usize = 60;
+const ENEMY_TTL: f32 = 600.0;
+// Shots and enemies fade out over their last frames
+const FADE_FRAMES: f32 = 20.0;
+// Enemies tumble at up to this many degrees per frame
+const ENEMY_SPIN: f32 = 3.0;
+// The hero glyph faces up
+const HERO_FACING: f32 = -90.0;
+const SHIELDS: usize = 3;
+// Shields circle this far from the hero, in shield glyph units
+const SHIELD_ORBIT: f32 = 250.0;
+const SHIELD_SPIN: f32 = 2.0;
+// Chains of parents longer than this are cut like cycles
+const MAX_DEPTH: usize = 16;
+const CURSOR_PULSE: f32 = 1.2;
+// Frames each way
+const CURSOR_PULSE_FRAMES: f32 = 30.0;
+const POPUP_RISE: f32 = 60.0;
+const POPUP_FRAMES: f32 = 40.0;
+// Sent when a projectile hits an enemy
+const HIT: &str = "hit";
+// Enemies flash this colour while a shot passes through them
+const HIT_COLOR: Color = Color{ r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+const HIT_FRAMES: f32 = 12.0;
+
+// The hero melts into `melt` as it spends its size, and loops `clip`
+// while it has one
+pub fn spawn_hero(world: &mut World, glyph: GlyphId, clip: Option<ClipId>, melt: GlyphId, pos: Vector, area: Rectangle) -> Entity {
+    let hero = world.spawn();
+    world.positions.insert(hero, pos);
+    world.velocities.insert(hero, Vector{ x: 0.0, y: 0.0 });
+    world.frictions.insert(hero, 0.9);
+    world.wraps.insert(hero, area);
+    world.sprites.insert(hero, Sprite{ z: 10.0, morph_to: Some(melt), ..Sprite::new(glyph) });
+    world.facings.insert(hero, HERO_FACING);
+    world.flashes.insert(hero, Flash{ color: FLASH_COLOR, frames: 0.0 });
+    world.heroes.insert(hero, Hero{ cooldown: 0.0 });
+    if let Some(clip) = clip {
+        world.animations.insert(hero, Animation::new(clip, Mode::Loop));
+    }
+    hero
+}
+
+// Shields circling `parent`, drawn just above it, a demo of attaching
+// entities to others
+pub fn spawn_shields(world: &mut World, glyph: GlyphId, parent: Entity) -> Vec<Entity> {
+    let mut shields = Vec::with_capacity(SHIELDS);
+    for i in 0..SHIELDS {
+        let shield = world.spawn();
+        world.positions.insert(shield, Vector{ x: 0.0, y: 0.0 });
+        world.spins.insert(shield, SHIELD_SPIN);
+        world.sprites.insert(shield, Sprite{
+            scale: Vector{ x: 0.3, y: 0.3 },
+            rotation: 360.0 * i as f32 / SHIELDS as f32,
+            // Rotating around a point below the glyph swings it around
+            pivot: Vector{ x: 0.0, y: SHIELD_ORBIT },
+            z: 0.5,
+            ..Sprite::new(glyph)
+        });
+        world.parents.insert(shield, Parent{ entity: parent, cascade: true });
+        shields.push(shield);
+    }
+    shields
+}
+
+// Floats up from `pos` and fades away
+pub fn spawn_popup(world: &mut World, glyph: GlyphId, pos: Vector) -> Entity {
+    let popup = world.spawn();
+    world.positions.insert(popup, pos);
+    world.sprites.insert(popup, Sprite{ z: 5.0, ..Sprite::new(glyph) });
+    world.tweens.insert(popup, Tween::parallel(vec![
+        Tween::to(Property::Position(pos - Vector{ x: 0.0, y: POPUP_RISE }), POPUP_FRAMES, Ease::QuadOut),
+        Tween::delay(POPUP_FRAMES / 2.0)
+            .then(Tween::to(Property::Alpha(0.0), POPUP_FRAMES / 2.0, Ease::QuadIn)),
+    ]).then(Tween::event(tween::DESPAWN)));
+    popup
+}
+
+pub fn spawn_cursor(world: &mut World, glyph: GlyphId) -> Entity {
+    let cursor = world.spawn();
+    let pos = Vector{ x: -1000.0, y: -1000.0 };
+    world.positions.insert(cursor, pos);
+    world.sprites.insert(cursor, Sprite{ z: 10.0, visible: false, ..Sprite::new(glyph) });
+    world.tweens.insert(cursor, Tween::sequence(vec![
+        Tween::to(Property::Scale(Vector{ x: 1.0, y: 1.0 } * CURSOR_PULSE), CURSOR_PULSE_FRAMES, Ease::SineInOut),
+        Tween::to(Property::Scale(Vector{ x: 1.0, y: 1.0 }), CURSOR_PULSE_FRAMES, Ease::SineInOut),
+    ]).looped(None));
+    world.cursors.insert(cursor, Cursor{ last_pos: pos, idle: 0 });
+    cursor
+}
+
+// Something that drifts across the screen and disappears after a while
+pub fn spawn_drifter(world: &mut World, glyph: GlyphId, pos: Vector, velocity: Vector, scale: f32, spin: f32) -> Entity {
+    let drifter = world.spawn();
+    world.positions.insert(drifter, pos);
+    world.velocities.insert(drifter, velocity);
+    world.spins.insert(drifter, spin);
+    world.lifetimes.insert(drifter, ENEMY_TTL);
+    world.fades.insert(drifter, FADE_FRAMES);
+    world.sprites.insert(drifter, Sprite{
+        scale: Vector{x:1.0, y:1.0} * scale,
+        z: 1.0,
+        ..Sprite::new(glyph)
+    });
+    drifter
+}
+
+pub fn friction(world: &mut World) {
+    let World{ ref mut velocities, ref frictions, .. } = *world;
+    for (entity, friction) in frictions.iter() {
+        if let Some(v) = velocities.get_mut(entity) {
+            *v *= *friction;
+            if v.len() < REST_SPEED {
+                *v = Vector{ x: 0.0, y: 0.0 };
+            }
+        }
+    }
+}
+
+pub fn steer(world: &mut World, input: &Input) {
+    let World{ ref mut velocities, ref heroes, .. } = *world;
+    for (entity, _) in heroes.iter() {
+        if let Some(v) = velocities.get_mut(entity) {
+            v.x += ACCELERATION * input.x;
+            v.y += ACCELERATION * input.y;
+        }
+    }
+}
+
+pub fn movement(world: &mut World, time_scale: f32) {
+    let World{ ref mut positions, ref velocities, .. } = *world;
+    for (entity, v) in velocities.iter() {
+        if let Some(pos) = positions.get_mut(entity) {
+            *pos += *v * time_scale;
+        }
+    }
+}
+
+pub fn spin(world: &mut World, time_scale: f32) {
+    let World{ ref mut sprites, ref spins, .. } = *world;
+    for (entity, spin) in spins.iter() {
+        if let Some(sprite) = sprites.get_mut(entity) {
+            sprite.rotation = (sprite.rotation + spin * time_scale) % 360.0;
+        }
+    }
+}
+
+pub fn face(world: &mut World) {
+    let World{ ref mut sprites, ref velocities, ref facings, .. } = *world;
+    for (entity, forward) in facings.iter() {
+        if let (Some(sprite), Some(velocity)) = (sprites.get_mut(entity), velocities.get(entity)) {
+            sprite.face(*velocity, *forward);
+        }
+    }
+}
+
+// The transform and z of `entity` through its parents, or None if they
+// loop back on themselves or go deeper than MAX_DEPTH
+fn resolve(entity: Entity, positions: &Storage<Vector>, sprites: &Storage<Sprite>, parents: &Storage<Parent>) -> Option<(Affine, f32)> {
+    let mut chain = [entity; MAX_DEPTH];
+    let mut transform = Affine::IDENTITY;
+    let mut z = 0.0;
+    let mut node = Some(entity);
+    for depth in 0..=MAX_DEPTH {
+        let current = match node {
+            Some(current) => current,
+            None => return Some((transform, z)),
+        };
+        if depth == MAX_DEPTH || chain[..depth].contains(&current) {
+            return None;
+        }
+        chain[depth] = current;
+        let pos = positions.get(current).cloned().unwrap_or(Vector{ x: 0.0, y: 0.0 });
+        match sprites.get(current) {
+            Some(sprite) => {
+                transform = sprite.transform(pos) * transform;
+                z += sprite.z;
+            },
+            None => transform = Affine::translate(pos) * transform,
+        }
+        node = parents.get(current).map(|p| p.entity);
+    }
+    Some((transform, z))
+}
+
+// Resolves every transform and z through the entity's parents. Entities
+// whose parents form a cycle are detached from theirs, which breaks it.
+// Returns how many were detached.
+pub fn transforms(world: &mut World) -> usize {
+    let World{ ref positions, ref sprites, ref mut parents, ref mut transforms, .. } = *world;
+    let zero = Vector{ x: 0.0, y: 0.0 };
+    let mut detached = 0;
+    for (entity, _) in positions.iter() {
+        let (transform, z) = match resolve(entity, positions, sprites, parents) {
+            Some(resolved) => resolved,
+            None => {
+                detached += 1;
+                parents.remove(entity);
+                resolve(entity, positions, sprites, parents).unwrap_or((Affine::IDENTITY, 0.0))
+            },
+        };
+        let pivot = sprites.get(entity).map(|s| s.pivot).unwrap_or(zero);
+        transforms.insert(entity, WorldTransform{ transform: transform, pos: transform * pivot, z: z });
+    }
+    detached
+}
+
+pub fn wrap(world: &mut World) {
+    let World{ ref mut positions, ref wraps, .. } = *world;
+    for (entity, area) in wraps.iter() {
+        if let Some(pos) = positions.get_mut(entity) {
+            let max = area.pos + area.size;
+            if pos.x > max.x {
+                pos.x = area.pos.x;
+            } else if pos.x < area.pos.x {
+                pos.x = max.x;
+            }
+            if pos.y > max.y {
+                pos.y = area.pos.y;
+            } else if pos.y < area.pos.y {
+                pos.y = max.y;
+            }
+        }
+    }
+}
+
+pub fn lifetime(world: &mut World, time_scale: f32) {
+    let World{ ref mut lifetimes, ref mut despawned, .. } = *world;
+    for (entity, ttl) in lifetimes.iter_mut() {
+        if *ttl <= 0.0 {
+            despawned.push(entity);
+        } else {
+            *ttl -= time_scale;
+        }
+    }
+}
+
+// Fires coins towards `target` while shooting, at a cost to the hero's
+// size unless `free`. They spin with `clip` if the sheet has one.
+pub fn shoot(world: &mut World, input: &Input, target: Vector, free: bool, coin: GlyphId, clip: Option<ClipId>, time_scale: f32) {
+    // Taken out so coins can be spawned while going through the heroes
+    let mut heroes = mem::replace(&mut world.heroes, Storage::new());
+    for (entity, hero) in heroes.iter_mut() {
+        let (pos, velocity) = match (world.positions.get(entity), world.velocities.get(entity)) {
+            (Some(pos), Some(velocity)) => (*pos, *velocity),
+            _ => continue,
+        };
+        let scale = world.sprites.get(entity).map(|s| s.scale).unwrap_or(Vector{ x: 1.0, y: 1.0 });
+        // Straight up with the target right on the hero
+        let aim = if target == pos { Vector{ x: 0.0, y: -1.0 } } else { (target - pos).normalize() };
+
+        if hero.cooldown > 0.0 {
+            hero.cooldown -= time_scale;
+        } else if input.shoot {
+            let mut size = scale;
+            if !free {
+                size *= SHOT_COST;
+                // Shrinks by the time it can shoot again, then regrows
+                // once it stops
+                let regrow = (1.0 / size.y).ln() / REGROWTH.ln();
+                let melted = ((1.0 - size.y) / MELTED).min(1.0);
+                world.tweens.insert(entity, Tween::sequence(vec![
+                    Tween::parallel(vec![
+                        Tween::to(Property::Scale(size), SHOT_COOLDOWN, Ease::QuadOut),
+                        Tween::to(Property::Morph(melted), SHOT_COOLDOWN, Ease::QuadOut),
+                    ]),
+                    Tween::delay(REGROW_DELAY),
+                    Tween::parallel(vec![
+                        Tween::to(Property::Scale(Vector{ x: 1.0, y: 1.0 }), regrow, Ease::SineInOut),
+                        Tween::to(Property::Morph(0.0), regrow, Ease::SineInOut),
+                    ]),
+                ]));
+            }
+            hero.cooldown = SHOT_COOLDOWN;
+            if let Some(flash) = world.flashes.get_mut(entity) {
+                flash.frames = FLASH_FRAMES;
+            }
+            let shot = world.spawn();
+            world.positions.insert(shot, pos + aim * SHOT_OFFSET);
+            world.velocities.insert(shot, velocity + aim * SHOT_SPEED);
+            world.lifetimes.insert(shot, SHOT_TTL);
+            world.fades.insert(shot, FADE_FRAMES);
+            world.sprites.insert(shot, Sprite{ scale: size, z: 1.0, ..Sprite::new(coin) });
+            world.projectiles.insert(shot, ());
+            if let Some(clip) = clip {
+                world.animations.insert(shot, Animation::new(clip, Mode::PingPong));
+            }
+        }
+    }
+    world.heroes = heroes;
+}
+
+pub fn flash(world: &mut World, time_scale: f32) {
+    for (_, flash) in world.flashes.iter_mut() {
+        flash.frames = (flash.frames - time_scale).max(0.0);
+    }
+}
+
+pub fn cursor(world: &mut World, mouse: Vector) {
+    let World{ ref mut positions, ref mut sprites, ref mut cursors, .. } = *world;
+    for (entity, cursor) in cursors.iter_mut() {
+        let visible = if mouse != cursor.last_pos {
+            cursor.last_pos = mouse;
+            cursor.idle = CURSOR_IDLE;
+            if let Some(pos) = positions.get_mut(entity) {
+                *pos = mouse;
+            }
+            true
+        } else if cursor.idle > 0 {
+            cursor.idle -= 1;
+            true
+        } else {
+            false
+        };
+        if let Some(sprite) = sprites.get_mut(entity) {
+            sprite.visible = visible;
+        }
+    }
+}
+
+// Sends a wave of enemies in from the top and sides every `period` frames
+pub fn spawn_enemies(world: &mut World, rng: &mut StdRng, size: Vector, time_scale: f32) {
+    let mut spawners = mem::replace(&mut world.spawners, Storage::new());
+    for (_, spawner) in spawners.iter_mut() {
+        if spawner.cooldown > 0.0 {
+            spawner.cooldown -= time_scale;
+            continue;
+        }
+        spawner.cooldown = spawner.period;
+        for (pos, velocity) in [
+            (
+                Vector{ x: rng.gen_range(0.0, size.x), y: -100.0 },
+                Vector{ x: rng.gen_range(-5.0, 5.0), y: rng.gen_range(1.0, 5.0) },
+            ),
+            (
+                Vector{ x: -100.0, y: rng.gen_range(0.0, size.y) },
+                Vector{ x: rng.gen_range(1.0, 5.0), y: rng.gen_range(1.0, 5.0) },
+            ),
+            (
+                Vector{ x: size.x + 100.0, y: rng.gen_range(0.0, size.y) },
+                Vector{ x: rng.gen_range(-5.0, -1.0), y: rng.gen_range(1.0, 5.0) },
+            ),
+        ].iter() {
+            let scale = rng.gen_range(0.2, 1.0);
+            let spin = rng.gen_range(-ENEMY_SPIN, ENEMY_SPIN);
+            let enemy = spawn_drifter(world, spawner.glyph, *pos, *velocity, scale, spin);
+            world.enemies.insert(enemy, ());
+            if let Some(clip) = spawner.clip {
+                world.animations.insert(enemy, Animation::new(clip, Mode::Once));
+            }
+        }
+    }
+    world.spawners = spawners;
+}
+
+// Sends HIT to every enemy a projectile overlaps, leaving what happens
+// next to systems::events
+pub fn collide(world: &mut World, glyphs: &GlyphSet) {
+    let World{ ref transforms, ref sprites, ref projectiles, ref enemies, ref mut events, .. } = *world;
+    let shape = |entity| match (transforms.get(entity), sprites.get(entity)) {
+        (Some(placed), Some(sprite)) => {
+            let glyph = glyphs.get_id(sprite.glyph);
+            Some((placed.bounds(glyph), placed.corners(glyph)))
+        },
+        _ => None,
+    };
+    for (projectile, _) in projectiles.iter() {
+        let (bounds, corners) = match shape(projectile) {
+            Some(shape) => shape,
+            None => continue,
+        };
+        for (enemy, _) in enemies.iter() {
+            match shape(enemy) {
+                Some((enemy_bounds, enemy_corners))
+                    if bounds.overlaps(&enemy_bounds) && collide::overlaps(&corners, &enemy_corners) => {
+                    events.push((enemy, HIT));
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+pub fn tweens(world: &mut World, time_scale: f32) {
+    let World{ ref mut tweens, ref mut positions, ref mut sprites, ref mut events, .. } = *world;
+    for (entity, tween) in tweens.iter_mut() {
+        if let (Some(pos), Some(sprite)) = (positions.get_mut(entity), sprites.get_mut(entity)) {
+            tween.step(time_scale, pos, sprite, &mut |name| events.push((entity, name)));
+        }
+    }
+}
+
+pub fn animate(world: &mut World, glyphs: &GlyphSet, time_scale: f32) {
+    let World{ ref mut animations, ref mut sprites, ref mut events, .. } = *world;
+    for (entity, animation) in animations.iter_mut() {
+        if let Some(sprite) = sprites.get_mut(entity) {
+            let clip = glyphs.get_clip(animation.clip);
+            if let Some(glyph) = animation.step(clip, time_scale, &mut |name| events.push((entity, name))) {
+                sprite.glyph = glyph;
+            }
+        }
+    }
+}
+
+// Reacts to this frame's events, then forgets them
+pub fn events(world: &mut World, popup: GlyphId) {
+    let mut events = mem::replace(&mut world.events, Vec::new());
+    for (entity, name) in events.drain(..) {
+        match name {
+            tween::DESPAWN => world.despawned.push(entity),
+            HIT => {
+                // Shots take a few frames to pass through an enemy, and
+                // only the first of them counts
+                if world.flashes.get(entity).map(|f| f.frames > 0.0).unwrap_or(false) {
+                    continue;
+                }
+                world.flashes.insert(entity, Flash{ color: HIT_COLOR, frames: HIT_FRAMES });
+                if let Some(placed) = world.transforms.get(entity).cloned() {
+                    spawn_popup(world, popup, placed.pos);
+                }
+            },
+            _ => (),
+        }
+    }
+    // Handing the buffer back keeps its capacity
+    world.events = events;
+}
+
+pub fn labels(world: &mut World, glyphs: &GlyphSet) {
+    for (_, label) in world.labels.iter_mut() {
+        label.update(glyphs);
+    }
+}
+
+pub fn draw_sprites(world: &World, glyphs: &GlyphSet, morphs: &mut Morph, window: &mut Window) {
+    for (entity, sprite) in world.sprites.iter() {
+        let placed = match world.transforms.get(entity) {
+            Some(placed) if sprite.visible => placed,
+            _ => continue,
+        };
+        let glyph = match sprite.morph_to {
+            Some(to) if sprite.morph > 0.0 => morphs.get(glyphs, sprite.glyph, to, sprite.morph),
+            _ => glyphs.get_id(sprite.glyph),
+        };
+        let (mode, color) = match world.flashes.get(entity) {
+            Some(flash) if flash.frames > 0.0 => (ColorMode::Replace, flash.color),
+            _ => (ColorMode::Tint, sprite.color(world.lifetimes.get(entity), world.fades.get(entity))),
+        };
+        window.draw_ex(
+            &glyph.painted(mode).transformed(placed.transform),
+            Col(color),
+            Transform::IDENTITY,
+            placed.z
+        );
+    }
+}
+
+pub fn draw_labels(world: &World, window: &mut Window) {
+    for (_, label) in world.labels.iter() {
+        label.draw(window, HUD_Z);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(world: &mut World, pos: Vector) -> Entity {
+        let entity = world.spawn();
+        world.positions.insert(entity, pos);
+        entity
+    }
+
+    #[test]
+    fn children_are_placed_through_their_parents() {
+        let mut world = World::new();
+        let parent = at(&mut world, Vector{ x: 10.0, y: 0.0 });
+        let child = at(&mut world, Vector{ x: 0.0, y: 5.0 });
+        world.parents.insert(child, Parent{ entity: parent, cascade: false });
+        transforms(&mut world);
+        assert_eq!(world.transforms.get(child).unwrap().pos, Vector{ x: 10.0, y: 5.0 });
+    }
+
+    #[test]
+    fn parent_cycles_are_broken() {
+        let mut world = World::new();
+        let a = at(&mut world, Vector{ x: 1.0, y: 0.0 });
+        let b = at(&mut world, Vector{ x: 0.0, y: 2.0 });
+        world.parents.insert(a, Parent{ entity: b, cascade: false });
+        world.parents.insert(b, Parent{ entity: a, cascade: false });
+        assert_eq!(transforms(&mut world), 1);
+        // `a` comes first, so it's the one let go
+        assert!(world.parents.get(a).is_none());
+        assert!(world.parents.get(b).is_some());
+        assert_eq!(world.transforms.get(a).unwrap().pos, Vector{ x: 1.0, y: 0.0 });
+        assert_eq!(world.transforms.get(b).unwrap().pos, Vector{ x: 1.0, y: 2.0 });
+    }
+
+    #[test]
+    fn entities_parented_to_themselves_are_detached() {
+        let mut world = World::new();
+        let a = at(&mut world, Vector{ x: 3.0, y: 4.0 });
+        world.parents.insert(a, Parent{ entity: a, cascade: false });
+        assert_eq!(transforms(&mut world), 1);
+        assert!(world.parents.get(a).is_none());
+        assert_eq!(world.transforms.get(a).unwrap().pos, Vector{ x: 3.0, y: 4.0 });
+    }
+
+    #[test]
+    fn chains_deeper_than_max_depth_are_cut() {
+        let mut world = World::new();
+        let mut parent = at(&mut world, Vector{ x: 0.0, y: 0.0 });
+        for _ in 0..MAX_DEPTH {
+            let child = at(&mut world, Vector{ x: 1.0, y: 0.0 });
+            world.parents.insert(child, Parent{ entity: parent, cascade: false });
+            parent = child;
+        }
+        assert_eq!(transforms(&mut world), 1);
+        assert!(world.parents.get(parent).is_none());
+        assert_eq!(world.transforms.get(parent).unwrap().pos, Vector{ x: 1.0, y: 0.0 });
+    }
+}
@@ -0,0 +1,334 @@
+use quicksilver::{
+    geom::{Rectangle, Transform, Vector},
+    graphics::{Background, Color, Drawable, Mesh},
+};
+
+use crate::glyph::{self, Glyph, GlyphSet};
+use crate::markup::{self, Content};
+
+// Height of a line of text at a scale of 1, matching the glyph sheet's grid
+pub const LINE_HEIGHT: f32 = 100.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+// Where a glyph's origin goes once the text is laid out, and how it's drawn
+pub struct Placement<'a> {
+    pub glyph: &'a Glyph,
+    pub pos: Vector,
+    pub scale: f32,
+    pub tint: Option<Color>,
+}
+
+// A character or icon of the text with its markup resolved
+#[derive(Clone)]
+struct Item<'a> {
+    // None for icons
+    c: Option<char>,
+    glyph: &'a Glyph,
+    // Line height in screen units
+    size: f32,
+    tint: Option<Color>,
+}
+
+impl<'a> Item<'a> {
+    fn is(&self, c: char) -> bool {
+        self.c == Some(c)
+    }
+
+    fn scale(&self) -> f32 {
+        self.size / LINE_HEIGHT
+    }
+}
+
+pub struct TextLayout {
+    // Height of a line in screen units
+    pub size: f32,
+    pub align: Align,
+    // Lines are wrapped at spaces to fit this width
+    pub max_width: Option<f32>,
+    // Added between letters, in screen units
+    pub letter_spacing: f32,
+    // Multiplier of the tallest glyph's size between the tops of lines
+    pub line_spacing: f32,
+}
+
+impl TextLayout {
+    pub fn new(size: f32) -> TextLayout {
+        TextLayout{
+            size: size,
+            align: Align::Left,
+            max_width: None,
+            letter_spacing: 0.0,
+            line_spacing: 1.0,
+        }
+    }
+
+    pub fn align(self, align: Align) -> TextLayout {
+        TextLayout{ align: align, ..self }
+    }
+
+    pub fn max_width(self, max_width: f32) -> TextLayout {
+        TextLayout{ max_width: Some(max_width), ..self }
+    }
+
+    pub fn letter_spacing(self, letter_spacing: f32) -> TextLayout {
+        TextLayout{ letter_spacing: letter_spacing, ..self }
+    }
+
+    pub fn line_spacing(self, line_spacing: f32) -> TextLayout {
+        TextLayout{ line_spacing: line_spacing, ..self }
+    }
+
+    // Pen movement after `item` when followed by `next`
+    fn advance(&self, item: &Item, next: Option<&Item>) -> f32 {
+        let kerning = next.and_then(|n| item.glyph.kerning.get(&n.glyph.name)).cloned().unwrap_or(0.0);
+        (item.glyph.advance + kerning) * item.scale() + self.letter_spacing
+    }
+
+    fn width(&self, line: &[Item]) -> f32 {
+        // Trailing spaces don't count towards alignment or wrapping
+        let end = line.iter().rposition(|item| !item.is(' ')).map(|i| i + 1).unwrap_or(0);
+        let line = &line[..end];
+        let mut width = 0.0;
+        for (i, item) in line.iter().enumerate() {
+            if i + 1 == line.len() {
+                width += item.glyph.advance * item.scale();
+            } else {
+                width += self.advance(item, Some(&line[i + 1]));
+            }
+        }
+        width
+    }
+
+    fn items<'a>(&self, text: &str, glyphs: &'a GlyphSet) -> Vec<Item<'a>> {
+        markup::parse(text).into_iter().map(|token| {
+            let set = token.style.set.as_ref().map(|set| set.as_str());
+            let (c, glyph) = match token.content {
+                Content::Char(c) => (Some(c), glyphs.get_char_in(c, set)),
+                Content::Icon(ref name) => (None, glyphs.get_in(name, set)),
+            };
+            Item{
+                c: c,
+                glyph: glyph,
+                size: self.size * token.style.size,
+                tint: token.style.color,
+            }
+        }).collect()
+    }
+
+    fn lines<'a>(&self, text: &str, glyphs: &'a GlyphSet) -> Vec<Vec<Item<'a>>> {
+        let items = self.items(text, glyphs);
+        let mut lines = Vec::new();
+        for paragraph in items.split(|item| item.is('\n')) {
+            let mut line: Vec<Item<'a>> = Vec::new();
+            let mut start = 0;
+            while start < paragraph.len() {
+                // The next word, along with the space before it
+                let end = paragraph[start + 1..].iter().position(|item| item.is(' '))
+                    .map(|i| start + 1 + i)
+                    .unwrap_or(paragraph.len());
+                let word = &paragraph[start..end];
+                let mut candidate = line.clone();
+                candidate.extend_from_slice(word);
+
+                let fits = match self.max_width {
+                    Some(max_width) => self.width(&candidate) <= max_width,
+                    None => true,
+                };
+                if fits || line.is_empty() {
+                    line = candidate;
+                } else {
+                    lines.push(line);
+                    line = word.iter().skip_while(|item| item.is(' ')).cloned().collect();
+                }
+                start = end;
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub fn place<'a>(&self, text: &str, glyphs: &'a GlyphSet) -> Vec<Placement<'a>> {
+        let lines = self.lines(text, glyphs);
+        let widths: Vec<f32> = lines.iter().map(|line| self.width(line)).collect();
+        let block_width = self.max_width.unwrap_or_else(||
+            widths.iter().cloned().fold(0.0, f32::max));
+
+        let mut placements = Vec::new();
+        let mut y = 0.0;
+        for (row, line) in lines.iter().enumerate() {
+            let mut x = match self.align {
+                Align::Left => 0.0,
+                Align::Center => (block_width - widths[row]) / 2.0,
+                Align::Right => block_width - widths[row],
+            };
+            // Lines are as tall as their largest glyph, and smaller ones
+            // sit on its bottom
+            let height = line.iter().map(|item| item.size).fold(self.size, f32::max);
+            for (i, item) in line.iter().enumerate() {
+                // Pen positions are the top left of each glyph's bounds
+                let top = y + height - item.size;
+                placements.push(Placement{
+                    glyph: item.glyph,
+                    pos: Vector{ x: x, y: top } - item.glyph.bounds.pos * item.scale(),
+                    scale: item.scale(),
+                    tint: item.tint,
+                });
+                x += self.advance(item, line.get(i + 1));
+            }
+            y += height * self.line_spacing;
+        }
+        placements
+    }
+
+    // Bounds of the laid out glyphs, relative to where the text is drawn
+    pub fn measure(&self, text: &str, glyphs: &GlyphSet) -> Rectangle {
+        let mut min = Vector{ x: 0.0, y: 0.0 };
+        let mut max = Vector{ x: 0.0, y: 0.0 };
+        for (i, placement) in self.place(text, glyphs).iter().enumerate() {
+            let top_left = placement.pos + placement.glyph.bounds.pos * placement.scale;
+            let bottom_right = top_left + placement.glyph.bounds.size * placement.scale;
+            if i == 0 {
+                min = top_left;
+                max = bottom_right;
+            }
+            min = Vector{ x: min.x.min(top_left.x), y: min.y.min(top_left.y) };
+            max = Vector{ x: max.x.max(bottom_right.x), y: max.y.max(bottom_right.y) };
+        }
+        Rectangle{ pos: min, size: max - min }
+    }
+
+    pub fn build(&self, text: &str, background: Background, glyphs: &GlyphSet) -> Glyph {
+        let mut mesh = Mesh::new();
+        for placement in self.place(text, glyphs) {
+            let transform =
+                Transform::translate(placement.pos) *
+                Transform::scale(Vector{x:placement.scale, y:placement.scale});
+            let start = mesh.vertices.len();
+            placement.glyph.draw(&mut mesh, background, transform, 0.0);
+            if let Some(tint) = placement.tint {
+                for vertex in mesh.vertices[start..].iter_mut() {
+                    vertex.col = glyph::tint(vertex.col, tint);
+                }
+            }
+        }
+        Glyph::from_mesh(text.to_owned(), mesh, self.measure(text, glyphs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `a` and `b` are 10 wide and a space 5, all a line tall, with `a`
+    // kerned 2 closer to a following `b`
+    fn glyphs() -> GlyphSet {
+        let mut glyphs = GlyphSet::new().unwrap();
+        for (name, width) in [("a", 10.0), ("b", 10.0), (" ", 5.0)].iter() {
+            let bounds = Rectangle::new(Vector{ x: 0.0, y: 0.0 }, Vector{ x: *width, y: LINE_HEIGHT });
+            let mut glyph = Glyph::from_mesh(name.to_string(), Mesh::new(), bounds);
+            if *name == "a" {
+                glyph.kerning.insert("b".to_owned(), -2.0);
+            }
+            glyphs.insert(name, glyph);
+        }
+        glyphs
+    }
+
+    fn positions(layout: &TextLayout, text: &str) -> Vec<(String, Vector)> {
+        layout.place(text, &glyphs()).iter()
+            .map(|p| (p.glyph.name.clone(), p.pos))
+            .collect()
+    }
+
+    fn xs(layout: &TextLayout, text: &str) -> Vec<f32> {
+        positions(layout, text).iter().map(|(_, pos)| pos.x).collect()
+    }
+
+    #[test]
+    fn kerning_applies_between_its_pair_only() {
+        let layout = TextLayout::new(LINE_HEIGHT);
+        assert_eq!(xs(&layout, "ab"), vec![0.0, 8.0]);
+        assert_eq!(xs(&layout, "ba"), vec![0.0, 10.0]);
+        assert_eq!(xs(&layout, "aab"), vec![0.0, 10.0, 18.0]);
+    }
+
+    #[test]
+    fn letter_spacing_adds_after_every_glyph() {
+        let layout = TextLayout::new(LINE_HEIGHT).letter_spacing(3.0);
+        assert_eq!(xs(&layout, "aba"), vec![0.0, 11.0, 24.0]);
+    }
+
+    #[test]
+    fn size_scales_advances_and_kerning() {
+        let layout = TextLayout::new(LINE_HEIGHT / 2.0);
+        let glyphs = glyphs();
+        let placed = layout.place("ab", &glyphs);
+        assert_eq!(placed[1].pos.x, 4.0);
+        assert_eq!(placed[1].scale, 0.5);
+    }
+
+    #[test]
+    fn alignment_is_within_the_widest_line() {
+        // The first line is 18 wide with its kerning, the second 10
+        let left = positions(&TextLayout::new(LINE_HEIGHT), "ab\nb");
+        let center = positions(&TextLayout::new(LINE_HEIGHT).align(Align::Center), "ab\nb");
+        let right = positions(&TextLayout::new(LINE_HEIGHT).align(Align::Right), "ab\nb");
+        assert_eq!(left[2].1, Vector{ x: 0.0, y: LINE_HEIGHT });
+        assert_eq!(center[2].1, Vector{ x: 4.0, y: LINE_HEIGHT });
+        assert_eq!(right[2].1, Vector{ x: 8.0, y: LINE_HEIGHT });
+        for placed in [&left, &center, &right].iter() {
+            assert_eq!(placed[0].1.x, 0.0);
+        }
+    }
+
+    #[test]
+    fn alignment_ignores_trailing_spaces() {
+        let right = xs(&TextLayout::new(LINE_HEIGHT).align(Align::Right).max_width(30.0), "b  ");
+        assert_eq!(right[0], 20.0);
+    }
+
+    #[test]
+    fn wraps_at_spaces_dropping_them() {
+        let layout = TextLayout::new(LINE_HEIGHT).max_width(25.0);
+        let placed = positions(&layout, "aa aa aa");
+        let names: Vec<&str> = placed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["a", "a", "a", "a", "a", "a"]);
+        for (line, pair) in placed.chunks(2).enumerate() {
+            assert_eq!(pair[0].1, Vector{ x: 0.0, y: line as f32 * LINE_HEIGHT });
+            assert_eq!(pair[1].1, Vector{ x: 10.0, y: line as f32 * LINE_HEIGHT });
+        }
+    }
+
+    #[test]
+    fn words_wider_than_max_width_get_a_line_of_their_own() {
+        let layout = TextLayout::new(LINE_HEIGHT).max_width(15.0);
+        let ys: Vec<f32> = positions(&layout, "aaa b").iter().map(|(_, pos)| pos.y).collect();
+        assert_eq!(ys, vec![0.0, 0.0, 0.0, LINE_HEIGHT]);
+    }
+
+    #[test]
+    fn words_that_fit_stay_on_the_line() {
+        let layout = TextLayout::new(LINE_HEIGHT).max_width(25.0);
+        assert_eq!(xs(&layout, "a b"), vec![0.0, 10.0, 15.0]);
+    }
+
+    #[test]
+    fn line_spacing_multiplies_the_line_height() {
+        let layout = TextLayout::new(LINE_HEIGHT).line_spacing(1.5);
+        let ys: Vec<f32> = positions(&layout, "a\na\na").iter().map(|(_, pos)| pos.y).collect();
+        assert_eq!(ys, vec![0.0, 150.0, 300.0]);
+    }
+
+    #[test]
+    fn measure_covers_the_laid_out_glyphs() {
+        let bounds = TextLayout::new(LINE_HEIGHT).measure("ab\nb", &glyphs());
+        assert_eq!(bounds.pos, Vector{ x: 0.0, y: 0.0 });
+        assert_eq!(bounds.size, Vector{ x: 18.0, y: 2.0 * LINE_HEIGHT });
+    }
+}
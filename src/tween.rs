@@ -0,0 +1,465 @@
+use std::f32::consts::PI;
+
+use quicksilver::{
+    geom::Vector,
+    graphics::Color,
+};
+
+use crate::sprite::Sprite;
+
+// Despawns the entity once reached, see Tween::event
+pub const DESPAWN: &str = "despawn";
+
+// Easing curves, mapping how far through a tween it is onto how far the
+// value has moved, both from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ease {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    // Overshoots a little before settling
+    BackIn,
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+// How far the Back curves overshoot, about 10%
+const BACK: f32 = 1.70158;
+
+fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+impl Ease {
+    // By the lower case name, as typed into the console
+    pub fn from_name(name: &str) -> Option<Ease> {
+        Some(match name {
+            "linear" => Ease::Linear,
+            "quadin" => Ease::QuadIn,
+            "quadout" => Ease::QuadOut,
+            "quadinout" => Ease::QuadInOut,
+            "cubicin" => Ease::CubicIn,
+            "cubicout" => Ease::CubicOut,
+            "cubicinout" => Ease::CubicInOut,
+            "sinein" => Ease::SineIn,
+            "sineout" => Ease::SineOut,
+            "sineinout" => Ease::SineInOut,
+            "backin" => Ease::BackIn,
+            "backout" => Ease::BackOut,
+            "elasticout" => Ease::ElasticOut,
+            "bounceout" => Ease::BounceOut,
+            _ => return None,
+        })
+    }
+
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Ease::Linear => t,
+            Ease::QuadIn => t * t,
+            Ease::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::QuadInOut => if t < 0.5 {
+                2.0 * t * t
+            } else {
+                1.0 - (2.0 - 2.0 * t).powi(2) / 2.0
+            },
+            Ease::CubicIn => t * t * t,
+            Ease::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Ease::CubicInOut => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+            },
+            Ease::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Ease::SineOut => (t * PI / 2.0).sin(),
+            Ease::SineInOut => (1.0 - (t * PI).cos()) / 2.0,
+            Ease::BackIn => (BACK + 1.0) * t * t * t - BACK * t * t,
+            Ease::BackOut => 1.0 + (BACK + 1.0) * (t - 1.0).powi(3) + BACK * (t - 1.0).powi(2),
+            Ease::ElasticOut => if t <= 0.0 || t >= 1.0 {
+                t
+            } else {
+                2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * 2.0 * PI / 3.0).sin() + 1.0
+            },
+            Ease::BounceOut => bounce_out(t),
+        }
+    }
+}
+
+// A value a tween moves towards. It starts from whatever the property is
+// when the tween gets to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    Position(Vector),
+    Scale(Vector),
+    Rotation(f32),
+    Tint(Color),
+    // Only the tint's alpha
+    Alpha(f32),
+    Z(f32),
+    // How far the sprite has turned into its `morph_to` glyph
+    Morph(f32),
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp_vector(a: Vector, b: Vector, t: f32) -> Vector {
+    Vector{ x: lerp(a.x, b.x, t), y: lerp(a.y, b.y, t) }
+}
+
+impl Property {
+    // The current value of the same property
+    fn read(&self, pos: &Vector, sprite: &Sprite) -> Property {
+        match *self {
+            Property::Position(_) => Property::Position(*pos),
+            Property::Scale(_) => Property::Scale(sprite.scale),
+            Property::Rotation(_) => Property::Rotation(sprite.rotation),
+            Property::Tint(_) => Property::Tint(sprite.tint),
+            Property::Alpha(_) => Property::Alpha(sprite.tint.a),
+            Property::Z(_) => Property::Z(sprite.z),
+            Property::Morph(_) => Property::Morph(sprite.morph),
+        }
+    }
+
+    fn write(&self, pos: &mut Vector, sprite: &mut Sprite) {
+        match *self {
+            Property::Position(v) => *pos = v,
+            Property::Scale(v) => sprite.scale = v,
+            Property::Rotation(r) => sprite.rotation = r,
+            Property::Tint(c) => sprite.tint = c,
+            Property::Alpha(a) => sprite.tint.a = a,
+            Property::Z(z) => sprite.z = z,
+            Property::Morph(m) => sprite.morph = m,
+        }
+    }
+
+    // `t` can go past 0 and 1 with the Back and Elastic curves
+    fn lerp(from: Property, to: Property, t: f32) -> Property {
+        match (from, to) {
+            (Property::Position(a), Property::Position(b)) => Property::Position(lerp_vector(a, b, t)),
+            (Property::Scale(a), Property::Scale(b)) => Property::Scale(lerp_vector(a, b, t)),
+            (Property::Rotation(a), Property::Rotation(b)) => Property::Rotation(lerp(a, b, t)),
+            (Property::Tint(a), Property::Tint(b)) => Property::Tint(Color{
+                r: lerp(a.r, b.r, t),
+                g: lerp(a.g, b.g, t),
+                b: lerp(a.b, b.b, t),
+                a: lerp(a.a, b.a, t),
+            }),
+            (Property::Alpha(a), Property::Alpha(b)) => Property::Alpha(lerp(a, b, t)),
+            (Property::Z(a), Property::Z(b)) => Property::Z(lerp(a, b, t)),
+            (Property::Morph(a), Property::Morph(b)) => Property::Morph(lerp(a, b, t)),
+            _ => to,
+        }
+    }
+}
+
+enum Step {
+    To{ to: Property, from: Option<Property>, frames: f32, ease: Ease, elapsed: f32 },
+    Delay{ frames: f32, elapsed: f32 },
+    Event(&'static str),
+    Sequence{ steps: Vec<Tween>, current: usize },
+    Parallel(Vec<Tween>),
+    // Forever without a count
+    Repeat{ tween: Box<Tween>, times: Option<usize>, count: usize },
+}
+
+// Animates a sprite and its position over a number of frames. Built from
+// `to`, `delay` and `event` steps combined in sequence or in parallel.
+pub struct Tween {
+    step: Step,
+    done: bool,
+}
+
+impl Tween {
+    fn new(step: Step) -> Tween {
+        Tween{ step: step, done: false }
+    }
+
+    pub fn to(property: Property, frames: f32, ease: Ease) -> Tween {
+        Tween::new(Step::To{ to: property, from: None, frames: frames, ease: ease, elapsed: 0.0 })
+    }
+
+    pub fn delay(frames: f32) -> Tween {
+        Tween::new(Step::Delay{ frames: frames, elapsed: 0.0 })
+    }
+
+    // Sends `name` to the game's events when reached
+    pub fn event(name: &'static str) -> Tween {
+        Tween::new(Step::Event(name))
+    }
+
+    pub fn sequence(steps: Vec<Tween>) -> Tween {
+        Tween::new(Step::Sequence{ steps: steps, current: 0 })
+    }
+
+    // Done once all of them are
+    pub fn parallel(tweens: Vec<Tween>) -> Tween {
+        Tween::new(Step::Parallel(tweens))
+    }
+
+    pub fn then(self, next: Tween) -> Tween {
+        Tween::sequence(vec![self, next])
+    }
+
+    // Plays `times` times, or forever with None
+    pub fn looped(self, times: Option<usize>) -> Tween {
+        Tween::new(Step::Repeat{ tween: Box::new(self), times: times, count: 0 })
+    }
+
+    fn reset(&mut self) {
+        self.done = false;
+        match self.step {
+            Step::To{ ref mut from, ref mut elapsed, .. } => {
+                *from = None;
+                *elapsed = 0.0;
+            },
+            Step::Delay{ ref mut elapsed, .. } => *elapsed = 0.0,
+            Step::Event(_) => (),
+            Step::Sequence{ ref mut steps, ref mut current } => {
+                *current = 0;
+                for step in steps.iter_mut() {
+                    step.reset();
+                }
+            },
+            Step::Parallel(ref mut tweens) => {
+                for tween in tweens.iter_mut() {
+                    tween.reset();
+                }
+            },
+            Step::Repeat{ ref mut tween, ref mut count, .. } => {
+                *count = 0;
+                tween.reset();
+            },
+        }
+    }
+
+    // Moves `dt` frames on, returning whether the tween has finished
+    pub fn step(&mut self, dt: f32, pos: &mut Vector, sprite: &mut Sprite, emit: &mut dyn FnMut(&'static str)) -> bool {
+        if self.done {
+            return true;
+        }
+        self.done = match self.step {
+            Step::To{ to, ref mut from, frames, ease, ref mut elapsed } => {
+                let start = *from.get_or_insert_with(|| to.read(pos, sprite));
+                *elapsed += dt;
+                let t = if frames > 0.0 { (*elapsed / frames).min(1.0) } else { 1.0 };
+                Property::lerp(start, to, ease.apply(t)).write(pos, sprite);
+                *elapsed >= frames
+            },
+            Step::Delay{ frames, ref mut elapsed } => {
+                *elapsed += dt;
+                *elapsed >= frames
+            },
+            Step::Event(name) => {
+                emit(name);
+                true
+            },
+            Step::Sequence{ ref mut steps, ref mut current } => {
+                // Steps that finish straight away don't hold up the next one
+                let mut dt = dt;
+                while *current < steps.len() && steps[*current].step(dt, pos, sprite, emit) {
+                    *current += 1;
+                    dt = 0.0;
+                }
+                *current == steps.len()
+            },
+            Step::Parallel(ref mut tweens) => {
+                let mut done = true;
+                for tween in tweens.iter_mut() {
+                    done &= tween.step(dt, pos, sprite, emit);
+                }
+                done
+            },
+            Step::Repeat{ ref mut tween, times, ref mut count } => {
+                if tween.step(dt, pos, sprite, emit) {
+                    *count += 1;
+                    tween.reset();
+                }
+                times.map(|times| *count >= times).unwrap_or(false)
+            },
+        };
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glyph::GlyphSet;
+
+    const EASES: [Ease; 14] = [
+        Ease::Linear,
+        Ease::QuadIn, Ease::QuadOut, Ease::QuadInOut,
+        Ease::CubicIn, Ease::CubicOut, Ease::CubicInOut,
+        Ease::SineIn, Ease::SineOut, Ease::SineInOut,
+        Ease::BackIn, Ease::BackOut,
+        Ease::ElasticOut,
+        Ease::BounceOut,
+    ];
+
+    fn sprite() -> Sprite {
+        Sprite::new(GlyphSet::new().unwrap().id("missing"))
+    }
+
+    // Steps `tween` one frame at a time, returning the events sent on
+    // each frame until it finishes or `frames` run out
+    fn run(tween: &mut Tween, frames: usize, pos: &mut Vector, sprite: &mut Sprite) -> Vec<Vec<&'static str>> {
+        let mut log = Vec::new();
+        for _ in 0..frames {
+            let mut events = Vec::new();
+            let done = tween.step(1.0, pos, sprite, &mut |name| events.push(name));
+            log.push(events);
+            if done {
+                break;
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn eases_start_at_0_and_end_at_1() {
+        for ease in EASES.iter() {
+            assert!(ease.apply(0.0).abs() < 1e-6, "{:?}", ease);
+            assert!((ease.apply(1.0) - 1.0).abs() < 1e-6, "{:?}", ease);
+        }
+    }
+
+    #[test]
+    fn in_outs_are_halfway_at_the_middle() {
+        for ease in [Ease::Linear, Ease::QuadInOut, Ease::CubicInOut, Ease::SineInOut].iter() {
+            assert!((ease.apply(0.5) - 0.5).abs() < 1e-6, "{:?}", ease);
+        }
+    }
+
+    #[test]
+    fn back_and_elastic_overshoot() {
+        // Back pulls away before setting off, or goes past before settling
+        assert!(Ease::BackIn.apply(0.2) < 0.0);
+        assert!(Ease::BackOut.apply(0.8) > 1.0);
+        assert!(Ease::ElasticOut.apply(0.1) > 1.0);
+    }
+
+    #[test]
+    fn bounce_stays_within_0_and_1() {
+        for i in 0..=100 {
+            let t = Ease::BounceOut.apply(i as f32 / 100.0);
+            assert!(t >= 0.0 && t <= 1.0 + 1e-6, "{} at {}", t, i);
+        }
+        // Touches down at the end of the first bounce
+        assert!((Ease::BounceOut.apply(1.0 / 2.75) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn eases_are_found_by_name() {
+        for ease in EASES.iter() {
+            let name = format!("{:?}", ease).to_lowercase();
+            assert_eq!(Ease::from_name(&name), Some(*ease));
+        }
+        assert_eq!(Ease::from_name("wobble"), None);
+    }
+
+    #[test]
+    fn rotation_tint_and_z_are_tweened() {
+        let mut pos = Vector{ x: 0.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::parallel(vec![
+            Tween::to(Property::Rotation(90.0), 2.0, Ease::Linear),
+            Tween::to(Property::Tint(Color{ r: 0.0, g: 0.5, b: 1.0, a: 0.0 }), 2.0, Ease::Linear),
+            Tween::to(Property::Z(4.0), 2.0, Ease::Linear),
+        ]);
+        assert!(!tween.step(1.0, &mut pos, &mut sprite, &mut |_| ()));
+        assert_eq!(sprite.rotation, 45.0);
+        assert_eq!(sprite.tint, Color{ r: 0.5, g: 0.75, b: 1.0, a: 0.5 });
+        assert_eq!(sprite.z, 2.0);
+        assert!(tween.step(1.0, &mut pos, &mut sprite, &mut |_| ()));
+        assert_eq!(sprite.rotation, 90.0);
+        assert_eq!(sprite.tint, Color{ r: 0.0, g: 0.5, b: 1.0, a: 0.0 });
+        assert_eq!(sprite.z, 4.0);
+    }
+
+    #[test]
+    fn to_starts_from_the_current_value() {
+        let mut pos = Vector{ x: 10.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::to(Property::Position(Vector{ x: 20.0, y: 0.0 }), 2.0, Ease::QuadIn);
+        assert!(!tween.step(1.0, &mut pos, &mut sprite, &mut |_| ()));
+        assert_eq!(pos, Vector{ x: 12.5, y: 0.0 });
+        assert!(tween.step(1.0, &mut pos, &mut sprite, &mut |_| ()));
+        assert_eq!(pos, Vector{ x: 20.0, y: 0.0 });
+    }
+
+    #[test]
+    fn sequence_runs_steps_in_order() {
+        let mut pos = Vector{ x: 0.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::sequence(vec![
+            Tween::event("a"),
+            Tween::delay(2.0),
+            Tween::event("b"),
+            Tween::to(Property::Alpha(0.0), 1.0, Ease::QuadOut),
+            Tween::event("c"),
+        ]);
+        let log = run(&mut tween, 10, &mut pos, &mut sprite);
+        // A step started by an instant one gets none of that frame
+        assert_eq!(log, vec![vec!["a"], vec![], vec!["b"], vec!["c"]]);
+        assert_eq!(sprite.tint.a, 0.0);
+    }
+
+    #[test]
+    fn parallel_is_done_once_all_are() {
+        let mut pos = Vector{ x: 0.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::parallel(vec![
+            Tween::delay(1.0).then(Tween::event("short")),
+            Tween::delay(3.0).then(Tween::event("long")),
+        ]);
+        let log = run(&mut tween, 10, &mut pos, &mut sprite);
+        assert_eq!(log, vec![vec!["short"], vec![], vec!["long"]]);
+        // Finished tweens don't play again
+        assert!(tween.step(1.0, &mut pos, &mut sprite, &mut |_| panic!("stepped after finishing")));
+    }
+
+    #[test]
+    fn repeat_plays_a_number_of_times() {
+        let mut pos = Vector{ x: 0.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::delay(1.0).then(Tween::event("tick")).looped(Some(3));
+        let log = run(&mut tween, 10, &mut pos, &mut sprite);
+        assert_eq!(log, vec![vec!["tick"]; 3]);
+    }
+
+    #[test]
+    fn repeat_restarts_to_from_its_new_value() {
+        let mut pos = Vector{ x: 0.0, y: 0.0 };
+        let mut sprite = sprite();
+        let mut tween = Tween::to(Property::Scale(Vector{ x: 2.0, y: 2.0 }), 1.0, Ease::QuadIn)
+            .then(Tween::to(Property::Scale(Vector{ x: 1.0, y: 1.0 }), 1.0, Ease::QuadIn))
+            .looped(None);
+        for frame in 0..100 {
+            assert!(!tween.step(1.0, &mut pos, &mut sprite, &mut |_| ()));
+            let expected = if frame % 2 == 0 { 2.0 } else { 1.0 };
+            assert_eq!(sprite.scale, Vector{ x: expected, y: expected });
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use quicksilver::geom::{Rectangle, Vector};
+
+use crate::anim::Animation;
+use crate::components::{Cursor, Flash, Hero, Parent, Spawner, WorldTransform};
+use crate::hud::Label;
+use crate::sprite::Sprite;
+use crate::tween::Tween;
+
+// A handle to something in the World. The generation tells apart entities
+// that reused the same slot, so a handle to a despawned entity finds
+// nothing rather than whatever replaced it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Entity {
+    index: usize,
+    generation: u32,
+}
+
+// One component type for every entity, indexed by the entity's slot
+pub struct Storage<T> {
+    items: Vec<Option<(u32, T)>>,
+}
+
+impl<T> Storage<T> {
+    pub fn new() -> Storage<T> {
+        Storage{ items: Vec::new() }
+    }
+
+    pub fn insert(&mut self, entity: Entity, item: T) {
+        while self.items.len() <= entity.index {
+            self.items.push(None);
+        }
+        self.items[entity.index] = Some((entity.generation, item));
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        match self.items.get(entity.index) {
+            Some(Some((generation, _))) if *generation == entity.generation => {
+                self.items[entity.index].take().map(|(_, item)| item)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.items.get(entity.index) {
+            Some(Some((generation, item))) if *generation == entity.generation => Some(item),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.items.get_mut(entity.index) {
+            Some(Some((generation, item))) if *generation == entity.generation => Some(item),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.items.iter().enumerate().filter_map(|(index, slot)| slot.as_ref()
+            .map(|(generation, item)| (Entity{ index: index, generation: *generation }, item)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.items.iter_mut().enumerate().filter_map(|(index, slot)| slot.as_mut()
+            .map(|(generation, item)| (Entity{ index: index, generation: *generation }, item)))
+    }
+}
+
+// Every game object is an entity with some of these components, and the
+// systems in systems.rs act on whichever ones they need
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<usize>,
+    // Children of the entities being despawned, to be detached
+    orphans: Vec<Entity>,
+    // Removed by `maintain` at the end of the frame, so systems can
+    // despawn while iterating over a storage
+    pub despawned: Vec<Entity>,
+    // Sent by tweens and systems during the frame, handled by
+    // systems::events
+    pub events: Vec<(Entity, &'static str)>,
+
+    // Relative to the parent, if any
+    pub positions: Storage<Vector>,
+    pub velocities: Storage<Vector>,
+    // Degrees per frame added to the sprite's rotation
+    pub spins: Storage<f32>,
+    // The sprite turns to face along its velocity. The value is the
+    // direction its glyph faces unrotated, in degrees.
+    pub facings: Storage<f32>,
+    // Multiplies the velocity every frame
+    pub frictions: Storage<f32>,
+    // Positions wrap around to the other side of this area
+    pub wraps: Storage<Rectangle>,
+    // Frames left to live
+    pub lifetimes: Storage<f32>,
+    // Fades the sprite out over this many of its last frames to live
+    pub fades: Storage<f32>,
+    pub sprites: Storage<Sprite>,
+    // Changes the sprite's glyph
+    pub animations: Storage<Animation>,
+    pub flashes: Storage<Flash>,
+    // Finished tweens stay until replaced
+    pub tweens: Storage<Tween>,
+    // Drawn in view space on top of the world
+    pub labels: Storage<Label>,
+    pub parents: Storage<Parent>,
+    pub transforms: Storage<WorldTransform>,
+    pub heroes: Storage<Hero>,
+    pub cursors: Storage<Cursor>,
+    pub spawners: Storage<Spawner>,
+    pub projectiles: Storage<()>,
+    pub enemies: Storage<()>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World{
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+            orphans: Vec::new(),
+            despawned: Vec::new(),
+            events: Vec::new(),
+            positions: Storage::new(),
+            velocities: Storage::new(),
+            spins: Storage::new(),
+            facings: Storage::new(),
+            frictions: Storage::new(),
+            wraps: Storage::new(),
+            lifetimes: Storage::new(),
+            fades: Storage::new(),
+            sprites: Storage::new(),
+            animations: Storage::new(),
+            flashes: Storage::new(),
+            tweens: Storage::new(),
+            labels: Storage::new(),
+            parents: Storage::new(),
+            transforms: Storage::new(),
+            heroes: Storage::new(),
+            cursors: Storage::new(),
+            spawners: Storage::new(),
+            projectiles: Storage::new(),
+            enemies: Storage::new(),
+        }
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index] = true;
+                Entity{ index: index, generation: self.generations[index] }
+            },
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity{ index: self.alive.len() - 1, generation: 0 }
+            },
+        }
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.alive.get(entity.index).cloned().unwrap_or(false)
+            && self.generations[entity.index] == entity.generation
+    }
+
+    // Removes the entities despawned this frame, and with them their
+    // children that cascade. The others are detached, keeping their
+    // position on screen.
+    pub fn maintain(&mut self) {
+        while let Some(entity) = self.despawned.pop() {
+            if !self.is_alive(entity) {
+                continue;
+            }
+            {
+                let World{ ref parents, ref mut despawned, ref mut orphans, .. } = *self;
+                for (child, parent) in parents.iter().filter(|(_, p)| p.entity == entity) {
+                    if parent.cascade {
+                        despawned.push(child);
+                    } else {
+                        orphans.push(child);
+                    }
+                }
+            }
+            while let Some(orphan) = self.orphans.pop() {
+                self.parents.remove(orphan);
+                // What it inherited from its parents becomes its own, so it
+                // stays as it was drawn
+                if let Some(placed) = self.transforms.get(orphan).cloned() {
+                    if let Some(pos) = self.positions.get_mut(orphan) {
+                        *pos = placed.pos;
+                    }
+                    if let Some(sprite) = self.sprites.get_mut(orphan) {
+                        sprite.set_transform(placed.transform);
+                        sprite.z = placed.z;
+                    }
+                }
+            }
+            self.positions.remove(entity);
+            self.velocities.remove(entity);
+            self.spins.remove(entity);
+            self.facings.remove(entity);
+            self.frictions.remove(entity);
+            self.wraps.remove(entity);
+            self.lifetimes.remove(entity);
+            self.fades.remove(entity);
+            self.sprites.remove(entity);
+            self.animations.remove(entity);
+            self.flashes.remove(entity);
+            self.tweens.remove(entity);
+            self.labels.remove(entity);
+            self.parents.remove(entity);
+            self.transforms.remove(entity);
+            self.heroes.remove(entity);
+            self.cursors.remove(entity);
+            self.spawners.remove(entity);
+            self.projectiles.remove(entity);
+            self.enemies.remove(entity);
+
+            self.alive[entity.index] = false;
+            self.generations[entity.index] += 1;
+            self.free.push(entity.index);
+        }
+    }
+
+    // Number of live entities
+    pub fn len(&self) -> usize {
+        self.alive.len() - self.free.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Parent;
+    use crate::glyph::GlyphSet;
+    use crate::systems;
+
+    #[test]
+    fn orphans_stay_as_they_were_drawn() {
+        let glyphs = GlyphSet::new().unwrap();
+        let mut world = World::new();
+        let parent = world.spawn();
+        world.positions.insert(parent, Vector{ x: 100.0, y: 50.0 });
+        world.sprites.insert(parent, Sprite{
+            rotation: 30.0,
+            scale: Vector{ x: 2.0, y: 0.5 },
+            skew: Vector{ x: 10.0, y: 0.0 },
+            z: 3.0,
+            ..Sprite::new(glyphs.id("missing"))
+        });
+        let child = world.spawn();
+        world.positions.insert(child, Vector{ x: 20.0, y: 0.0 });
+        world.sprites.insert(child, Sprite{
+            rotation: 45.0,
+            pivot: Vector{ x: 5.0, y: 5.0 },
+            z: 1.0,
+            ..Sprite::new(glyphs.id("missing"))
+        });
+        world.parents.insert(child, Parent{ entity: parent, cascade: false });
+        systems::transforms(&mut world);
+        let before = *world.transforms.get(child).unwrap();
+
+        world.despawned.push(parent);
+        world.maintain();
+        systems::transforms(&mut world);
+        let after = *world.transforms.get(child).unwrap();
+
+        assert!(!world.is_alive(parent) && world.is_alive(child));
+        assert!(world.parents.get(child).is_none());
+        assert_eq!(after.z, before.z);
+        for corner in [Vector{ x: 0.0, y: 0.0 }, Vector{ x: 10.0, y: 0.0 }, Vector{ x: 0.0, y: 10.0 }].iter() {
+            let (a, b) = (before.transform * *corner, after.transform * *corner);
+            assert!((a - b).len() < 1e-3, "{:?} moved from {:?} to {:?}", corner, a, b);
+        }
+    }
+
+    #[test]
+    fn children_that_cascade_go_with_their_parent() {
+        let mut world = World::new();
+        let parent = world.spawn();
+        let child = world.spawn();
+        world.parents.insert(child, Parent{ entity: parent, cascade: true });
+        world.despawned.push(parent);
+        world.maintain();
+        assert!(!world.is_alive(child));
+        assert_eq!(world.len(), 0);
+    }
+}